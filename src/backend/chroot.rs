@@ -0,0 +1,275 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use nix::sched::{self, CloneFlags};
+use nix::unistd::geteuid;
+
+use crate::helpers::{errln, infoln, rooted, ONYX_DIR};
+use crate::manifest::Bind;
+
+use super::{exit_code, lowerdir, net, pidns, spawn_error_code, uts, Backend, LaunchOpts, Session};
+
+//=== chroot backend ===//
+// root only: a kernel overlay over the box's layers with the user's delta
+// on top, private mounts for /proc, /dev and /sys, then a real chroot.
+
+//=== mount guard ===//
+struct MountGuard {
+    mounts: Vec<PathBuf>,
+    merged: PathBuf,
+    is_overlay: bool,
+}
+
+impl MountGuard {
+    /// `lowers` = rootfs layers, top to bottom (ONYX_DIR/sys/<system_name>, then any parents)
+    /// `uid`  = user id
+    /// `system_name` = e.g., "debian", "alpine"
+    /// `user_binds` = extra host mounts from the box manifest and the command line
+    fn new(lowers: &[PathBuf], uid: Option<&str>, system_name: &str, user_binds: &[Bind]) -> Result<Self, String> {
+        let mut is_overlay = false;
+
+        let merged = if let Some(uid) = uid {
+            is_overlay = true;
+
+            // NEW PATH LOGIC: ONYX_DIR/delta/<uid>/<system_name>/...
+            // this prevents different OSs from sharing the same 'upper' layer
+            let base = ONYX_DIR.join("delta").join(uid).join(system_name);
+            let upper = base.join("upper");
+            let work  = base.join("work");
+            let merged = base.join("merged");
+
+            for d in [&upper, &work, &merged] {
+                std::fs::create_dir_all(d)
+                    .map_err(|e| format!("failed to create {}: {}", d.display(), e))?;
+            }
+
+            let opts = format!(
+                "lowerdir={},upperdir={},workdir={}",
+                lowerdir(lowers),
+                upper.display(),
+                work.display()
+            );
+
+            run("mount", &["-t", "overlay", "overlay", "-o", &opts, merged.to_str().unwrap()])?;
+
+            merged
+        } else {
+            // without an overlay only a plain box can be entered directly
+            if lowers.len() > 1 {
+                return Err("derived boxes need an overlay mount".to_string());
+            }
+            lowers[0].clone()
+        };
+
+        let mut mounts = Vec::new();
+
+        // -- make / private to avoid mount leakage to host --
+        run("mount", &["--make-rprivate", "/"])?;
+
+        // bind mounts logic (proc, dev, sys)
+        let binds = vec![
+            ("proc", "proc", vec!["-t", "proc"]),
+            ("/dev", "dev", vec!["--bind"]),
+            ("/dev/pts", "dev/pts", vec!["--bind"]),
+            ("/sys", "sys", vec!["--bind"]),
+        ];
+
+        for (src, dest_rel, args) in binds {
+            let dest = merged.join(dest_rel);
+            std::fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+
+            // convert to string once so we don't keep fighting the borrow checker
+            let dest_str = dest.to_str().ok_or("invalid path")?.to_string();
+
+            let mut cmd_args = args.clone();
+            cmd_args.push(src);
+            cmd_args.push(&dest_str);
+
+            run("mount", &cmd_args)?;
+
+            // extra hardening for /sys and /dev using our string reference
+            if dest_rel == "sys" {
+                run("mount", &["-o", "remount,ro,bind", &dest_str])?;
+            }
+            if dest_rel == "dev" {
+                run("mount", &["--make-slave", &dest_str])?;
+            }
+
+            // NOW we move it. once it's in the vector, 'dest' is gone.
+            mounts.push(dest);
+        }
+
+        // user binds from the box manifest and the command line
+        for bind in user_binds {
+            let dest = merged.join(bind.guest.trim_start_matches('/'));
+
+            // the mount point has to match what's being mounted on it
+            if Path::new(&bind.host).is_dir() {
+                std::fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+            } else if dest.symlink_metadata().is_err() {
+                if let Some(parent) = dest.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                std::fs::File::create(&dest).map_err(|e| e.to_string())?;
+            }
+
+            // a symlink in the guest path must not lead the mount out of the box
+            let real = dest.canonicalize().map_err(|e| e.to_string())?;
+            let root = merged.canonicalize().map_err(|e| e.to_string())?;
+            if !real.starts_with(&root) {
+                return Err(format!("bind target {} resolves outside the box", bind.guest));
+            }
+
+            let dest_str = real.to_str().ok_or("invalid path")?.to_string();
+            run("mount", &["--bind", &bind.host, &dest_str])?;
+            mounts.push(real);
+            if bind.ro {
+                run("mount", &["-o", "remount,bind,ro", &dest_str])?;
+            }
+        }
+
+        Ok(Self { mounts, merged, is_overlay })
+    }
+    fn root(&self) -> &Path {
+        &self.merged
+    }
+}
+
+impl Drop for MountGuard {
+    fn drop(&mut self) {
+        // 1. reverse unmount children (pts -> dev -> sys -> proc)
+        for m in self.mounts.iter().rev() {
+            let _ = Command::new("umount").arg("-l").arg(m).status();
+        }
+
+        // 2. unmount the overlay itself
+        if self.is_overlay {
+            let _ = Command::new("umount").arg("-l").arg(&self.merged).status();
+            // 3. clean up the MERGED mountpoint directory
+            let _ = std::fs::remove_dir(&self.merged);
+        }
+    }
+}
+
+fn run(cmd: &str, args: &[&str]) -> Result<(), String> {
+    let status = Command::new(cmd)
+        .args(args)
+        .status()
+        .map_err(|e| format!("failed to run {}: {}", cmd, e))?;
+
+    if !status.success() {
+        return Err(format!("command failed: {} {:?}", cmd, args));
+    }
+    Ok(())
+}
+
+/// try to create a mount namespace. returns Ok(()) if success.
+/// on failure, returns Err with explanation.
+fn try_unshare_mount_ns() -> Result<(), String> {
+    match sched::unshare(CloneFlags::CLONE_NEWNS) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("unshare(CLONE_NEWNS) failed: {}", e)),
+    }
+}
+
+/// run `program` chrooted into `root` as the session's guest user, in its workdir
+fn chroot_command(root: &Path, program: &str, opts: &LaunchOpts) -> io::Result<Command> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+
+    let nul = |_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte");
+    let c_root = CString::new(root.as_os_str().as_bytes()).map_err(nul)?;
+    let c_workdir = CString::new(opts.workdir.as_bytes()).map_err(nul)?;
+    let guest = opts.guest.clone();
+    let groups: Vec<libc::gid_t> = guest.groups.iter().map(|g| *g as libc::gid_t).collect();
+
+    let mut cmd = Command::new(program);
+    // only raw syscalls in here: this runs between fork and exec
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::chroot(c_root.as_ptr()) != 0 || libc::chdir(c_workdir.as_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if (guest.uid != 0 || guest.gid != 0)
+                && (libc::setgroups(groups.len() as _, groups.as_ptr()) != 0
+                    || libc::setgid(guest.gid as libc::gid_t) != 0
+                    || libc::setuid(guest.uid as libc::uid_t) != 0)
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(cmd)
+}
+
+pub struct Chroot;
+
+impl Backend for Chroot {
+    fn name(&self) -> &'static str {
+        "chroot"
+    }
+
+    fn unavailable(&self, _session: &Session) -> Option<String> {
+        if !rooted() {
+            return Some("it needs root".to_string());
+        }
+        None
+    }
+
+    fn run(&self, session: &Session) -> Result<i32, String> {
+        let opts = &session.opts;
+
+        // try to unshare mount namespace first
+        match try_unshare_mount_ns() {
+            Ok(_) => infoln("box", "entered new mount namespace (isolation enabled)"),
+            Err(e) => {
+                // fallback: make mounts private on the host (best-effort)
+                errln("box", &format!("couldn't create mount namespace: {}. falling back to private mounts.", e));
+                if let Err(e2) = run("mount", &["--make-rprivate", "/"]) {
+                    return Err(format!("failed to make / rprivate: {}; refusing to proceed without isolation", e2));
+                }
+            }
+        }
+
+        net::enter(opts.isolate.net)?;
+        uts::enter(opts)?;
+
+        // RAII mount guard
+        let binds = session.binds()?;
+        let guard = MountGuard::new(&session.lowers(), Some(&geteuid().to_string()), &session.name, &binds)?;
+
+        if !guard.root().join(opts.workdir.trim_start_matches('/')).is_dir() {
+            return Err(format!("workdir '{}' does not exist in the box", opts.workdir));
+        }
+
+        let argv = session.argv();
+        let mut chroot = chroot_command(guard.root(), &argv[0], opts).map_err(|e| format!("chroot failed: {}", e))?;
+
+        chroot
+            .env_clear() // kill everything termux gave us
+            .envs(&opts.env)
+            .env_remove("LD_PRELOAD")
+            .args(&argv[1..]);
+
+        if opts.isolate.pid {
+            let code = pidns::run(&mut chroot, &guard.root().join("proc"))?;
+            infoln("box", "unmounting...");
+            return Ok(code);
+        }
+
+        let code = match chroot.status() {
+            Ok(status) => exit_code(status),
+            Err(e) => {
+                errln("box", &format!("failed to run '{}' in the box: {}", argv[0], e));
+                spawn_error_code(&e)
+            }
+        };
+
+        // when this returns, MountGuard is dropped and unmounts occur inside
+        infoln("box", "unmounting...");
+        Ok(code)
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+use crate::helpers::{errln, infoln, rooted, ONYX_DIR};
+use crate::manifest::Bind;
+
+mod chroot;
+mod net;
+mod pidns;
+mod proot;
+mod userns;
+mod uts;
+
+//=== launch backends ===//
+// the ways onyx can run something inside a box. `open` and `exec` describe
+// the session they want, pick a backend (or let `auto` pick one) and hand
+// over; the backend owns mounting, entering the rootfs and cleaning up.
+
+//=== exit codes ===//
+// `box exec` exits with the guest command's status, or 128+N if it was
+// killed by signal N. onyx's own failures use the codes below, the same
+// ones docker and podman use, so wrappers can tell them apart.
+
+/// onyx itself failed: bad arguments, missing box, mount or launch failure
+pub const EXIT_ONYX: i32 = 125;
+/// the command was found but couldn't be run (permissions, user switch)
+pub const EXIT_CANNOT_RUN: i32 = 126;
+/// the command (or the box's shell) doesn't exist
+pub const EXIT_NOT_FOUND: i32 = 127;
+
+/// the code a shell would report for `status`
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => 128 + sig,
+        _ => EXIT_ONYX,
+    }
+}
+
+/// the code for a child we failed to start at all
+fn spawn_error_code(e: &io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => EXIT_CANNOT_RUN,
+        // pre_exec failures (chroot, chdir, setuid) surface as the raw errno
+        _ if e.raw_os_error().is_some() => EXIT_CANNOT_RUN,
+        _ => EXIT_ONYX,
+    }
+}
+
+//=== sessions ===//
+/// who a session runs as inside the box
+#[derive(Debug, Clone)]
+pub struct GuestUser {
+    pub uid: u32,
+    pub gid: u32,
+    pub name: Option<String>,
+    pub home: String,
+    /// login shell from the guest's passwd, if any
+    pub shell: Option<String>,
+    pub groups: Vec<u32>,
+}
+
+impl GuestUser {
+    pub fn root() -> Self {
+        Self { uid: 0, gid: 0, name: Some("root".to_string()), home: "/root".to_string(), shell: None, groups: Vec::new() }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// proot's identity args: fake root, or the guest's uid:gid
+    fn proot_args(&self) -> Vec<String> {
+        if self.is_root() {
+            vec!["-0".to_string()]
+        } else {
+            vec!["-i".to_string(), format!("{}:{}", self.uid, self.gid)]
+        }
+    }
+}
+
+/// the network a session sees (`--net=`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Net {
+    /// the host's network stack, as before
+    #[default]
+    Host,
+    /// a network namespace with no usable interfaces
+    None,
+    /// a network namespace with just `lo` up
+    Loopback,
+}
+
+impl Net {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "host" => Ok(Net::Host),
+            "none" => Ok(Net::None),
+            "loopback" => Ok(Net::Loopback),
+            other => Err(format!("unknown network mode '{}' (use host, none or loopback)", other)),
+        }
+    }
+}
+
+/// namespaces a session gets on top of the mount namespace every backend uses
+#[derive(Debug, Clone, Default)]
+pub struct Isolation {
+    /// own pid namespace under onyx's init; strays die with the session
+    pub pid: bool,
+    pub net: Net,
+    /// own hostname
+    pub uts: bool,
+    /// own SysV IPC and POSIX message queues
+    pub ipc: bool,
+}
+
+impl Isolation {
+    /// add a comma separated `--isolate=` list
+    pub fn add(&mut self, list: &str) -> Result<(), String> {
+        for what in list.split(',').filter(|w| !w.is_empty()) {
+            match what {
+                "pid" => self.pid = true,
+                "uts" => self.uts = true,
+                "ipc" => self.ipc = true,
+                other => return Err(format!("unknown isolation '{}' (use pid, uts or ipc)", other)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// everything about a session that comes from flags and the box manifest
+pub struct LaunchOpts {
+    pub binds: Vec<Bind>,
+    pub env: BTreeMap<String, String>,
+    pub guest: GuestUser,
+    pub workdir: String,
+    /// the shell used for logins and `--shell` commands
+    pub shell: String,
+    pub isolate: Isolation,
+    /// the hostname under `--isolate=uts`
+    pub hostname: String,
+}
+
+/// what the session runs once it's inside the box
+pub enum Target {
+    /// an interactive shell
+    Login,
+    /// an exact argv, exec'd as is
+    Argv(Vec<String>),
+    /// a command line for the box's shell (`exec --shell`)
+    Script(String),
+}
+
+pub struct Session {
+    pub name: String,
+    /// the box's own layer, ONYX_DIR/sys/<name>
+    pub sys_path: PathBuf,
+    /// rootfs layers, top to bottom
+    pub layers: Vec<PathBuf>,
+    pub opts: LaunchOpts,
+    pub target: Target,
+}
+
+impl Session {
+    /// what actually gets exec'd in the box
+    fn argv(&self) -> Vec<String> {
+        match &self.target {
+            Target::Login => vec![self.opts.shell.clone()],
+            Target::Argv(argv) => argv.clone(),
+            Target::Script(line) => vec![self.opts.shell.clone(), "-c".to_string(), line.clone()],
+        }
+    }
+
+    /// the binds to set up: the user's, plus the generated /etc/hostname
+    /// and /etc/hosts under `--isolate=uts`
+    fn binds(&self) -> Result<Vec<Bind>, String> {
+        let mut binds = self.opts.binds.clone();
+        if self.opts.isolate.uts {
+            binds.extend(uts::view_binds(self)?);
+        }
+        Ok(binds)
+    }
+
+    /// layers for the overlay's lowerdir: the box's, plus the stub layer
+    /// under `--isolate=uts`. call `binds()` first, which creates it.
+    fn lowers(&self) -> Vec<PathBuf> {
+        let mut lowers = self.layers.clone();
+        if self.opts.isolate.uts {
+            lowers.insert(0, uts::stub_layer(self));
+        }
+        lowers
+    }
+
+    /// ONYX_DIR/delta/<uid>/<name> for the user running onyx
+    fn delta_dir(&self) -> PathBuf {
+        ONYX_DIR.join("delta").join(nix::unistd::geteuid().as_raw().to_string()).join(&self.name)
+    }
+}
+
+/// overlay `lowerdir=` value for a box's layers (top to bottom)
+fn lowerdir(layers: &[PathBuf]) -> String {
+    layers
+        .iter()
+        .map(|l| l.display().to_string())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+//=== backends ===//
+pub trait Backend {
+    fn name(&self) -> &'static str;
+
+    /// why this backend can't run `session` here, if it can't
+    fn unavailable(&self, session: &Session) -> Option<String>;
+
+    /// run the session. Ok is the guest's exit code; Err means the session
+    /// never got going and nothing ran in the box.
+    fn run(&self, session: &Session) -> Result<i32, String>;
+}
+
+pub const BACKENDS: [&str; 5] = ["auto", "chroot", "userns", "proot", "fuse-proot"];
+
+fn by_name(name: &str) -> Option<Box<dyn Backend>> {
+    match name {
+        "chroot" => Some(Box::new(chroot::Chroot)),
+        "userns" => Some(Box::new(userns::Userns)),
+        "proot" => Some(Box::new(proot::Proot)),
+        "fuse-proot" => Some(Box::new(proot::FuseProot)),
+        _ => None,
+    }
+}
+
+fn is_termux() -> bool {
+    std::env::var("PREFIX").map(|s| s.contains("com.termux")).unwrap_or(false)
+}
+
+/// the backends `auto` tries for `session` on this host, best first, and why
+fn auto(session: &Session) -> Vec<(&'static str, &'static str)> {
+    if rooted() {
+        return vec![("chroot", "running as root")];
+    }
+
+    let mut picks = Vec::new();
+    // userns only maps the caller, and the kernel overlay can't copy up files
+    // owned by anyone else (EOVERFLOW), so boxes made by root go to proot first
+    let userns = userns::available();
+    let owned = userns && userns::owned_by_caller(&session.layers);
+    if owned {
+        picks.push(("userns", "unprivileged user namespaces are available"));
+    }
+    // only termux without a usable /dev/fuse is stuck with plain proot
+    if !(is_termux() && std::path::Path::new("/dev/fuse").metadata().is_err()) {
+        picks.push(("fuse-proot", "proot with fuse-overlayfs for the delta"));
+    }
+    if userns && !owned {
+        picks.push(("userns", "fuse-proot can't run here; files you don't own can't be changed"));
+    }
+    picks.push(("proot", "no overlay available, so plain proot"));
+    picks
+}
+
+/// run `session` on the `requested` backend ("auto" to choose).
+/// returns the code `onyx` should exit with.
+pub fn launch(session: &Session, requested: &str) -> i32 {
+    let picks = match requested {
+        "auto" => auto(session),
+        other => vec![(other, "requested with --backend")],
+    };
+
+    // reasons for skipped backends only matter if nothing else works
+    let mut skipped = Vec::new();
+    for (name, reason) in picks {
+        let Some(backend) = by_name(name) else {
+            errln("box", &format!("unknown backend '{}' (use {})", name, BACKENDS.join(", ")));
+            return EXIT_ONYX;
+        };
+
+        if let Some(why) = backend.unavailable(session) {
+            skipped.push(format!("the {} backend can't run this box: {}", backend.name(), why));
+            continue;
+        }
+
+        infoln("box", &format!("backend: {} ({})", backend.name(), reason));
+        match backend.run(session) {
+            Ok(code) => return code,
+            Err(e) => errln("box", &format!("the {} session failed: {}", backend.name(), e)),
+        }
+    }
+
+    for why in skipped {
+        errln("box", &why);
+    }
+    EXIT_ONYX
+}
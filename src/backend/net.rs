@@ -0,0 +1,48 @@
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use nix::sched::{unshare, CloneFlags};
+
+use super::Net;
+
+//=== network isolation ===//
+// `--net=none` and `--net=loopback` put the session in a network namespace
+// of its own. it starts with nothing but a downed `lo`, so the box can't
+// reach the host or the outside world; `loopback` brings `lo` up so local
+// servers and tests still work.
+
+/// give the calling process the network `mode` asks for. in a user
+/// namespace this has to come after the unshare so the new one is ours.
+pub fn enter(mode: Net) -> Result<(), String> {
+    if mode == Net::Host {
+        return Ok(());
+    }
+    unshare(CloneFlags::CLONE_NEWNET).map_err(|e| format!("couldn't create a network namespace: {}", e))?;
+    if mode == Net::Loopback {
+        loopback_up().map_err(|e| format!("couldn't bring up lo: {}", e))?;
+    }
+    Ok(())
+}
+
+/// what `ip link set lo up` does
+fn loopback_up() -> std::io::Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in req.ifr_name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+    unsafe {
+        if libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFFLAGS as _, &mut req) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        if libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFFLAGS as _, &req) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+use nix::fcntl::OFlag;
+use nix::mount::{mount, MsFlags};
+use nix::sched::{clone, CloneFlags};
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{pipe2, Pid};
+
+use crate::helpers::errln;
+
+use super::{spawn_error_code, EXIT_ONYX};
+
+//=== pid isolation ===//
+// `--isolate=pid`: the command runs in its own pid namespace under a tiny
+// init (pid 1) forked from onyx. init passes signals sent with kill(2) on
+// to the command (the terminal's ^C and friends reach it on their own),
+// reaps whatever gets orphaned to it and, once the command exits, kills
+// everything left, so no daemon outlives the session or keeps the overlay
+// busy. if onyx itself dies, init goes with it and the kernel takes the
+// rest of the namespace down.
+
+/// signals onyx and init hand on instead of acting on themselves
+const FORWARDED: [Signal; 7] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGWINCH,
+];
+
+fn watched() -> SigSet {
+    let mut set = SigSet::empty();
+    for sig in FORWARDED {
+        set.add(sig);
+    }
+    set.add(Signal::SIGCHLD);
+    set
+}
+
+extern "C" fn ignore(_: libc::c_int) {}
+
+/// the next signal in `set`, and whether a process sent it. the tty's
+/// SIGINT, SIGQUIT and SIGWINCH come from the kernel and go to the whole
+/// foreground group, the command included, so passing those on would
+/// deliver them twice.
+fn next_signal(set: &SigSet) -> Option<(Signal, bool)> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let sig = unsafe { libc::sigwaitinfo(set.as_ref(), &mut info) };
+    if sig < 0 {
+        return None;
+    }
+    Signal::try_from(sig).ok().map(|s| (s, info.si_code != libc::SI_KERNEL))
+}
+
+/// the code a shell would report for a finished child
+fn code_of(status: WaitStatus) -> i32 {
+    match status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, sig, _) => 128 + sig as i32,
+        _ => EXIT_ONYX,
+    }
+}
+
+/// wait for `child` to finish, forwarding signals to it. `watched()` must
+/// already be blocked. init (`reap_all`) also collects every other process
+/// that exits, since orphans in the namespace are reparented to it.
+fn supervise(child: Pid, reap_all: bool) -> WaitStatus {
+    let set = watched();
+    loop {
+        match next_signal(&set) {
+            Some((Signal::SIGCHLD, _)) => loop {
+                let target = if reap_all { Pid::from_raw(-1) } else { child };
+                match waitpid(target, Some(WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::StillAlive) | Err(_) => break,
+                    Ok(status @ (WaitStatus::Exited(pid, _) | WaitStatus::Signaled(pid, _, _))) if pid == child => {
+                        return status;
+                    }
+                    Ok(_) => continue,
+                }
+            },
+            Some((sig, true)) => {
+                let _ = kill(child, sig);
+            }
+            Some((_, false)) | None => {}
+        }
+    }
+}
+
+/// pid 1 of the namespace: fresh /proc, then the command, then cleanup
+fn init(cmd: &mut Command, proc_dir: &Path, report: &mut fs::File) -> i32 {
+    // die with onyx rather than leave the box running unsupervised
+    unsafe {
+        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+    }
+
+    // the kernel drops signals from outside the namespace that pid 1 has no
+    // handler for. they stay blocked for sigwait, so this never runs
+    let handler = SigAction::new(SigHandler::Handler(ignore), SaFlags::empty(), SigSet::empty());
+    for sig in FORWARDED {
+        let _ = unsafe { sigaction(sig, &handler) };
+    }
+
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    if let Err(e) = mount(Some("proc"), proc_dir, Some("proc"), flags, None::<&str>) {
+        let _ = report.write_all(format!("failed to mount /proc for the pid namespace: {}", e).as_bytes());
+        return EXIT_ONYX;
+    }
+
+    // the command gets the signals init is waiting on, so it must not inherit the block
+    unsafe {
+        cmd.pre_exec(|| {
+            SigSet::empty().thread_set_mask().map_err(std::io::Error::from)
+        });
+    }
+
+    let code = match cmd.spawn() {
+        Ok(main) => code_of(supervise(Pid::from_raw(main.id() as i32), true)),
+        Err(e) => {
+            errln("box", &format!("failed to run {:?} in the box: {}", cmd.get_program(), e));
+            spawn_error_code(&e)
+        }
+    };
+
+    // the command is done; nothing else in here gets to stay
+    let _ = kill(Pid::from_raw(-1), Signal::SIGKILL);
+    while waitpid(Pid::from_raw(-1), None).is_ok() {}
+    code
+}
+
+/// run `cmd` as the only child of a new pid namespace's init, with a fresh
+/// proc mounted at `proc_dir`. Ok is the command's exit code; Err means
+/// the namespace couldn't be set up and nothing ran.
+pub fn run(cmd: &mut Command, proc_dir: &Path) -> Result<i32, String> {
+    let (read_end, write_end) = pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("pipe failed: {}", e))?;
+
+    // block first, or a quick SIGCHLD could slip by before we wait for it
+    let old_mask = watched().thread_swap_mask(SigmaskHow::SIG_BLOCK).map_err(|e| e.to_string())?;
+
+    let mut report = fs::File::from(write_end);
+    let mut stack = vec![0u8; 1 << 20];
+    let cb = Box::new(|| init(cmd, proc_dir, &mut report) as isize);
+    let spawned = unsafe { clone(cb, &mut stack, CloneFlags::CLONE_NEWPID, Some(libc::SIGCHLD)) };
+    drop(report);
+
+    let result = match spawned {
+        Err(e) => Err(format!("couldn't create a pid namespace: {}", e)),
+        Ok(pid) => {
+            let status = supervise(pid, false);
+            let mut setup_error = String::new();
+            let _ = fs::File::from(read_end).read_to_string(&mut setup_error);
+            if setup_error.is_empty() { Ok(code_of(status)) } else { Err(setup_error) }
+        }
+    };
+
+    let _ = old_mask.thread_set_mask();
+    result
+}
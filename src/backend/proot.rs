@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
+
+use crate::helpers::{infoln, ONYX_DIR};
+use crate::manifest::Bind;
+
+use super::{exit_code, lowerdir, Backend, Net, Session, EXIT_ONYX};
+
+//=== proot backends ===//
+// unprivileged sessions. `fuse-proot` mounts the layers plus the user's
+// delta with fuse-overlayfs inside a user namespace and runs proot on the
+// merged view; plain `proot` runs straight on the box's own layer, so
+// changes land in the box itself and derived boxes can't be entered.
+
+/// proot `-b` args for user binds. proot has no read-only binds, so
+/// `unavailable` refuses `:ro` ones before getting here.
+fn proot_bind_args(binds: &[Bind]) -> Vec<String> {
+    let mut out = Vec::new();
+    for bind in binds {
+        out.push("-b".to_string());
+        out.push(format!("{}:{}", bind.host, bind.guest));
+    }
+    out
+}
+
+/// everything proot needs after `-r <root>`, up to the guest argv.
+/// `binds` is `session.binds()`, which may write files, so callers pass it in.
+fn proot_args(session: &Session, binds: &[Bind]) -> Vec<String> {
+    let opts = &session.opts;
+    let mut args = opts.guest.proot_args();
+    args.extend(["-b", "/dev", "-b", "/proc", "-b", "/sys"].map(String::from));
+    args.extend(proot_bind_args(binds));
+    // proot emulates SysV IPC for its own tracees, so they don't meet the host's
+    if opts.isolate.ipc {
+        args.push("--sysvipc".to_string());
+    }
+    args.extend(["--link2symlink".to_string(), "-w".to_string(), opts.workdir.clone()]);
+    args
+}
+
+/// proot can't make a bind read-only; say so instead of silently ignoring it
+fn ro_bind(binds: &[Bind]) -> Option<String> {
+    binds
+        .iter()
+        .find(|b| b.ro)
+        .map(|b| format!("read-only bind {} needs the chroot backend (root); proot can't enforce it", b.guest))
+}
+
+/// proot sessions share the host's namespaces. ipc is emulated with
+/// `--sysvipc` and uts only gets the hostname files, not uname's answer.
+fn isolation(session: &Session) -> Option<String> {
+    if session.opts.isolate.pid {
+        return Some("proot can't isolate pids; use the chroot or userns backend".to_string());
+    }
+    if session.opts.isolate.net != Net::Host {
+        return Some("proot shares the host's network; --net=none and --net=loopback need the chroot or userns backend".to_string());
+    }
+    None
+}
+
+fn missing(component: &str) -> Option<String> {
+    let path = ONYX_DIR.join("bin").join(component);
+    if path.exists() {
+        return None;
+    }
+    Some(format!("{} is not installed (run 'onyx update')", path.display()))
+}
+
+/// single-quote `s` for the bash -c chains
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// `env -i` assignments for the proot at the end of a fuse-overlayfs chain
+fn chain_env(env: &BTreeMap<String, String>) -> String {
+    env.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .chain(std::iter::once(format!("PROOT_TMP_DIR={}", ONYX_DIR.join("tmp").display())))
+        .map(|kv| sh_quote(&kv))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the script `unshare ... bash -c` runs for fuse sessions: mount the
+/// overlay, give up with EXIT_ONYX if the shell never shows up in the merged
+/// view, then report "ok" on fd `status` and run proot on it. the guest argv
+/// is passed as the script's own arguments, so the host shell never parses
+/// any of it.
+fn proot_chain(session: &Session, binds: &[Bind], status: i32) -> String {
+    let delta_dir = session.delta_dir();
+    let merged = delta_dir.join("merged");
+
+    let fuse_opts = format!(
+        "lowerdir={},upperdir={},workdir={},squash_to_root",
+        lowerdir(&session.lowers()),
+        delta_dir.join("upper").display(),
+        delta_dir.join("work").display()
+    );
+    let args = proot_args(session, binds).iter().map(|a| sh_quote(a)).collect::<Vec<_>>().join(" ");
+
+    format!(
+        "{} -f -o {} {} {}>&- & sleep 1 && [ -e {} ] || exit {}; echo ok >&{}; exec {}>&-; env -i {} {} -r {} {} \"$@\"",
+        sh_quote(&ONYX_DIR.join("bin/fuse-overlayfs").to_string_lossy()),
+        sh_quote(&fuse_opts),
+        sh_quote(&merged.to_string_lossy()),
+        status,
+        sh_quote(&format!("{}{}", merged.display(), session.opts.shell)),
+        EXIT_ONYX,
+        status,
+        status,
+        chain_env(&session.opts.env),
+        sh_quote(&ONYX_DIR.join("bin/proot").to_string_lossy()),
+        sh_quote(&merged.to_string_lossy()),
+        args
+    )
+}
+
+pub struct Proot;
+
+impl Backend for Proot {
+    fn name(&self) -> &'static str {
+        "proot"
+    }
+
+    fn unavailable(&self, session: &Session) -> Option<String> {
+        if session.layers.len() > 1 {
+            return Some("derived boxes need an overlay; plain proot can't stack layers".to_string());
+        }
+        ro_bind(&session.opts.binds)
+            .or_else(|| isolation(session))
+            .or_else(|| missing("proot"))
+    }
+
+    fn run(&self, session: &Session) -> Result<i32, String> {
+        infoln("box", "no overlay: changes go straight into the box");
+
+        let binds = session.binds()?;
+
+        // on android, sys_path should be a writable copy of the rootfs
+        let status = Command::new(ONYX_DIR.join("bin/proot"))
+            .env_clear() // kill everything termux gave us
+            .envs(&session.opts.env)
+            .env("PROOT_TMP_DIR", ONYX_DIR.join("tmp"))
+            .arg("-r").arg(&session.sys_path)
+            .args(proot_args(session, &binds))
+            .args(session.argv())
+            .status()
+            .map_err(|e| format!("failed to run proot: {}", e))?;
+
+        Ok(exit_code(status))
+    }
+}
+
+pub struct FuseProot;
+
+impl Backend for FuseProot {
+    fn name(&self) -> &'static str {
+        "fuse-proot"
+    }
+
+    fn unavailable(&self, session: &Session) -> Option<String> {
+        ro_bind(&session.opts.binds)
+            .or_else(|| isolation(session))
+            .or_else(|| missing("proot"))
+            .or_else(|| missing("fuse-overlayfs"))
+    }
+
+    fn run(&self, session: &Session) -> Result<i32, String> {
+        let delta_dir = session.delta_dir();
+        for dir in ["upper", "work", "merged"] {
+            fs::create_dir_all(delta_dir.join(dir)).map_err(|e| format!("failed to create delta dirs: {}", e))?;
+        }
+
+        // a failing guest command must not look like a failed launch, so
+        // check that user namespaces work before handing over to the chain
+        let can_unshare = Command::new("unshare")
+            .args(["-U", "-r", "-m", "true"])
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if !can_unshare {
+            return Err("user namespaces are not available".to_string());
+        }
+
+        // the chain says "ok" here once the overlay is up. after that the exit
+        // status is the guest's, whatever it is, and must not look like a failed launch
+        let (read_end, write_end) =
+            pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK).map_err(|e| format!("pipe failed: {}", e))?;
+        let status_fd = write_end.as_raw_fd();
+
+        let binds = session.binds()?;
+        let mut cmd = Command::new("unshare");
+        cmd.args(["-U", "-r", "-m", "bash", "-c", &proot_chain(session, &binds, status_fd), "onyx"])
+            .args(session.argv());
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::fcntl(status_fd, libc::F_SETFD, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let status = cmd.status().map_err(|e| format!("failed to run unshare: {}", e))?;
+
+        drop(write_end);
+        let mut report = [0u8; 2];
+        if !matches!(fs::File::from(read_end).read(&mut report), Ok(2)) || &report != b"ok" {
+            return Err("fuse-overlayfs didn't come up".to_string());
+        }
+        Ok(exit_code(status))
+    }
+}
@@ -0,0 +1,260 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, getegid, geteuid, pipe2, pivot_root, ForkResult};
+use once_cell::sync::Lazy;
+
+use crate::helpers::errln;
+use crate::manifest;
+
+use super::{lowerdir, net, pidns, spawn_error_code, uts, Backend, Session, EXIT_ONYX};
+
+//=== userns backend ===//
+// rootless sessions without ptrace. a child process unshares a user and a
+// mount namespace, maps the caller onto the guest user, mounts the box's
+// layers and the delta with the kernel overlay, binds /dev, /proc and /sys
+// in and pivot_roots into the result. everything it mounted goes away with
+// the namespace when the session ends.
+//
+// only the caller's own uid/gid are mapped, so files owned by anyone else
+// (usually root, for a box created by root) show up as nobody inside and
+// can't be changed. `auto` prefers fuse-proot for such boxes.
+
+/// can this process create a user + mount namespace at all?
+pub fn available() -> bool {
+    *AVAILABLE
+}
+
+/// the probe forks, so it runs once per process
+static AVAILABLE: Lazy<bool> = Lazy::new(|| {
+    // a throwaway child, so the probe can't leave us inside a namespace
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            let ok = unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS).is_ok();
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+        }
+        Ok(ForkResult::Parent { child }) => matches!(waitpid(child, None), Ok(WaitStatus::Exited(_, 0))),
+        Err(_) => false,
+    }
+});
+
+/// paths spot-checked in boxes whose box.toml predates the recorded owner
+const OWNER_SAMPLES: [&str; 6] = ["", "etc", "etc/passwd", "usr", "usr/bin", "var"];
+
+/// is every entry in `layers` owned by the caller? only those can be
+/// copied up to the delta, since nothing else is mapped in the namespace.
+pub fn owned_by_caller(layers: &[PathBuf]) -> bool {
+    let (uid, gid) = (geteuid().as_raw(), getegid().as_raw());
+    let me = format!("{}:{}", uid, gid);
+    layers.iter().all(|layer| {
+        let name = layer.file_name().unwrap_or_default().to_string_lossy();
+        match manifest::load(&name).and_then(|m| m.owner) {
+            Some(owner) => owner == me,
+            None => OWNER_SAMPLES.iter().all(|rel| match fs::symlink_metadata(layer.join(rel)) {
+                Ok(m) => m.uid() == uid && m.gid() == gid,
+                Err(_) => true,
+            }),
+        }
+    })
+}
+
+/// map the caller's host ids onto the guest's ids in the new user namespace
+fn write_id_maps(guest_uid: u32, guest_gid: u32, host_uid: u32, host_gid: u32) -> Result<(), String> {
+    let write = |file: &str, data: String| {
+        fs::write(Path::new("/proc/self").join(file), data).map_err(|e| format!("failed to write {}: {}", file, e))
+    };
+    write("uid_map", format!("{} {} 1", guest_uid, host_uid))?;
+    // an unprivileged gid_map needs setgroups switched off first
+    write("setgroups", "deny".to_string())?;
+    write("gid_map", format!("{} {} 1", guest_gid, host_gid))
+}
+
+/// flags a read-only remount has to keep; the kernel locks them in a user namespace
+fn locked_flags(path: &Path) -> MsFlags {
+    let mut flags = MsFlags::empty();
+    let Ok(st) = nix::sys::statvfs::statvfs(path) else {
+        return flags;
+    };
+    let f = st.flags();
+    use nix::sys::statvfs::FsFlags;
+    for (have, keep) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if f.contains(have) {
+            flags |= keep;
+        }
+    }
+    flags
+}
+
+fn bind(src: &Path, dst: &Path, ro: bool) -> Result<(), String> {
+    mount(Some(src), dst, None::<&str>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&str>)
+        .map_err(|e| format!("failed to bind {} to {}: {}", src.display(), dst.display(), e))?;
+    if ro {
+        let flags = MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY | locked_flags(dst);
+        mount(None::<&str>, dst, None::<&str>, flags, None::<&str>)
+            .map_err(|e| format!("failed to make {} read-only: {}", dst.display(), e))?;
+    }
+    Ok(())
+}
+
+/// set up the namespace and the box's root, then pivot into it.
+/// runs in the forked child; on Ok the caller is inside the box.
+fn enter(session: &Session) -> Result<(), String> {
+    let opts = &session.opts;
+    let delta_dir = session.delta_dir();
+    let (upper, work, merged) = (delta_dir.join("upper"), delta_dir.join("work"), delta_dir.join("merged"));
+    for dir in [&upper, &work, &merged] {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    }
+
+    // delta_dir() goes by euid, so anything under it is resolved before the remap
+    let binds = session.binds()?;
+    let lowers = session.lowers();
+
+    let (host_uid, host_gid) = (geteuid().as_raw(), getegid().as_raw());
+    unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS).map_err(|e| format!("unshare failed: {}", e))?;
+    write_id_maps(opts.guest.uid, opts.guest.gid, host_uid, host_gid)?;
+    net::enter(opts.isolate.net)?;
+    uts::enter(opts)?;
+
+    // nothing below may leak back into the host's mount table
+    mount(None::<&str>, "/", None::<&str>, MsFlags::MS_REC | MsFlags::MS_PRIVATE, None::<&str>)
+        .map_err(|e| format!("failed to make / private: {}", e))?;
+
+    // overlay keeps its whiteouts in user.* xattrs when mounted in a user namespace
+    let base = format!("lowerdir={},upperdir={},workdir={}", lowerdir(&lowers), upper.display(), work.display());
+    mount(Some("overlay"), &merged, Some("overlay"), MsFlags::empty(), Some(format!("{},userxattr", base).as_str()))
+        .or_else(|_| mount(Some("overlay"), &merged, Some("overlay"), MsFlags::empty(), Some(base.as_str())))
+        .map_err(|e| format!("overlay mount failed: {}", e))?;
+
+    // a fresh proc needs a pid namespace of our own, so bind the host's
+    for (src, rel) in [("/dev", "dev"), ("/proc", "proc"), ("/sys", "sys")] {
+        let dest = merged.join(rel);
+        fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+        bind(Path::new(src), &dest, false)?;
+    }
+
+    // user binds from the box manifest and the command line
+    for b in &binds {
+        let dest = merged.join(b.guest.trim_start_matches('/'));
+
+        // the mount point has to match what's being mounted on it
+        if Path::new(&b.host).is_dir() {
+            fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+        } else if dest.symlink_metadata().is_err() {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::File::create(&dest).map_err(|e| e.to_string())?;
+        }
+
+        // a symlink in the guest path must not lead the mount out of the box
+        let real = dest.canonicalize().map_err(|e| e.to_string())?;
+        if !real.starts_with(merged.canonicalize().map_err(|e| e.to_string())?) {
+            return Err(format!("bind target {} resolves outside the box", b.guest));
+        }
+        bind(Path::new(&b.host), &real, b.ro)?;
+    }
+
+    if !merged.join(opts.workdir.trim_start_matches('/')).is_dir() {
+        return Err(format!("workdir '{}' does not exist in the box", opts.workdir));
+    }
+
+    // stack the new root on the old one, then drop the old one
+    std::env::set_current_dir(&merged).map_err(|e| e.to_string())?;
+    pivot_root(".", ".").map_err(|e| format!("pivot_root failed: {}", e))?;
+    umount2(".", MntFlags::MNT_DETACH).map_err(|e| format!("failed to detach the host root: {}", e))?;
+    std::env::set_current_dir("/").map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub struct Userns;
+
+impl Backend for Userns {
+    fn name(&self) -> &'static str {
+        "userns"
+    }
+
+    fn unavailable(&self, _session: &Session) -> Option<String> {
+        if !available() {
+            return Some("user namespaces are disabled on this host".to_string());
+        }
+        None
+    }
+
+    fn run(&self, session: &Session) -> Result<i32, String> {
+        // the child reports setup failures here; a successful exec closes it
+        let (read_end, write_end) = pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("pipe failed: {}", e))?;
+
+        let child = match unsafe { fork() } {
+            Err(e) => return Err(format!("fork failed: {}", e)),
+            Ok(ForkResult::Parent { child }) => child,
+            Ok(ForkResult::Child) => {
+                drop(read_end);
+                let mut report = fs::File::from(write_end);
+                if let Err(e) = enter(session) {
+                    let _ = report.write_all(e.as_bytes());
+                    unsafe { libc::_exit(EXIT_ONYX) }
+                }
+
+                let argv = session.argv();
+                let mut cmd = Command::new(&argv[0]);
+                cmd.args(&argv[1..]).env_clear().envs(&session.opts.env).current_dir(&session.opts.workdir);
+
+                if session.opts.isolate.pid {
+                    // init dies with this process; this one has to die with onyx
+                    unsafe {
+                        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                    }
+                    match pidns::run(&mut cmd, Path::new("/proc")) {
+                        Ok(code) => unsafe { libc::_exit(code) },
+                        Err(e) => {
+                            let _ = report.write_all(e.as_bytes());
+                            unsafe { libc::_exit(EXIT_ONYX) }
+                        }
+                    }
+                }
+                let err = cmd.exec();
+
+                // exec only comes back on failure, and that one is the guest's
+                errln("box", &format!("failed to run '{}' in the box: {}", argv[0], err));
+                unsafe { libc::_exit(spawn_error_code(&err)) }
+            }
+        };
+
+        drop(write_end);
+        let mut setup_error = String::new();
+        let _ = fs::File::from(read_end).read_to_string(&mut setup_error);
+
+        let status = loop {
+            match waitpid(child, None) {
+                Err(Errno::EINTR) => continue,
+                other => break other.map_err(|e| format!("waitpid failed: {}", e))?,
+            }
+        };
+
+        if !setup_error.is_empty() {
+            return Err(setup_error);
+        }
+        match status {
+            WaitStatus::Exited(_, code) => Ok(code),
+            WaitStatus::Signaled(_, sig, _) => Ok(128 + sig as i32),
+            other => Err(format!("session ended in an unexpected state: {:?}", other)),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nix::sched::{unshare, CloneFlags};
+use nix::unistd::sethostname;
+
+use crate::manifest::Bind;
+
+use super::{LaunchOpts, Session};
+
+//=== uts and ipc isolation ===//
+// `--isolate=uts` gives the session its own hostname (the box's `hostname`
+// setting, or its name) and `--isolate=ipc` its own SysV IPC objects and
+// POSIX message queues. the guest's /etc/hostname and /etc/hosts are
+// replaced by generated copies that agree with the new name. those live in
+// the delta's sessions dir, never in the upper, so they can't be committed.
+
+/// unshare what `opts` asks for and set the box's hostname. like
+/// `net::enter`, in a user namespace this goes after the unshare.
+pub fn enter(opts: &LaunchOpts) -> Result<(), String> {
+    let mut flags = CloneFlags::empty();
+    if opts.isolate.uts {
+        flags |= CloneFlags::CLONE_NEWUTS;
+    }
+    if opts.isolate.ipc {
+        flags |= CloneFlags::CLONE_NEWIPC;
+    }
+    if flags.is_empty() {
+        return Ok(());
+    }
+    unshare(flags).map_err(|e| format!("couldn't create uts/ipc namespaces: {}", e))?;
+
+    if opts.isolate.uts {
+        sethostname(&opts.hostname).map_err(|e| format!("couldn't set the hostname: {}", e))?;
+    }
+    Ok(())
+}
+
+/// the guest's /etc/hosts with the hostname pointed at 127.0.1.1, like debian does
+fn hosts_file(current: &str, hostname: &str) -> String {
+    let mut out = String::new();
+    if !current.lines().any(|l| l.split_whitespace().skip(1).any(|n| n == "localhost")) {
+        out.push_str("127.0.0.1\tlocalhost\n");
+    }
+    for line in current.lines() {
+        // any old 127.0.1.1 entry names the host (or a previous hostname)
+        if line.split_whitespace().next() != Some("127.0.1.1") {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out.push_str(&format!("127.0.1.1\t{}\n", hostname));
+    out
+}
+
+/// where a session's generated files go
+fn view_dir(session: &Session) -> PathBuf {
+    session.delta_dir().join("sessions").join("uts")
+}
+
+/// an extra lower layer with empty /etc/hostname and /etc/hosts for guests
+/// that lack them, so the binds have something to land on without putting
+/// anything in the upper
+pub fn stub_layer(session: &Session) -> PathBuf {
+    view_dir(session).join("stub")
+}
+
+/// write the session's /etc/hostname and /etc/hosts and return binds that
+/// lay them over the guest's own. they're regenerated every session, so
+/// edits from inside the box don't stick.
+pub fn view_binds(session: &Session) -> Result<Vec<Bind>, String> {
+    let dir = view_dir(session);
+    let stub = stub_layer(session);
+    fs::create_dir_all(&stub).map_err(|e| format!("failed to create {}: {}", stub.display(), e))?;
+
+    // the user's own delta may have edited the hosts file
+    let mut view = vec![session.delta_dir().join("upper")];
+    view.extend(session.layers.iter().cloned());
+    let current = crate::layer::find(&view, Path::new("etc/hosts"))
+        .and_then(|p| fs::read_to_string(p).ok())
+        .unwrap_or_default();
+
+    let hostname = &session.opts.hostname;
+    let mut binds = Vec::new();
+    for (file, contents) in [("hostname", format!("{}\n", hostname)), ("hosts", hosts_file(&current, hostname))] {
+        let rel = Path::new("etc").join(file);
+        if crate::layer::find(&view, &rel).is_none() {
+            make_stub(&stub, &view, &rel)?;
+        }
+
+        let host = dir.join(file);
+        fs::write(&host, contents).map_err(|e| format!("failed to write {}: {}", host.display(), e))?;
+        binds.push(Bind { host: host.to_string_lossy().to_string(), guest: format!("/etc/{}", file), ro: false });
+    }
+    Ok(binds)
+}
+
+/// an empty `rel` in the stub layer. its etc/ sits above the guest's, so it
+/// takes on that one's mode, owner and times.
+fn make_stub(stub: &Path, view: &[PathBuf], rel: &Path) -> Result<(), String> {
+    let etc = stub.join("etc");
+    if !etc.is_dir() {
+        fs::create_dir(&etc).map_err(|e| format!("failed to create {}: {}", etc.display(), e))?;
+        if let Some(guest_etc) = crate::layer::find(view, Path::new("etc"))
+            && let Ok(meta) = fs::symlink_metadata(&guest_etc)
+        {
+            let _ = crate::copy::copy_metadata(&guest_etc, &etc, &meta);
+        }
+    }
+    let path = stub.join(rel);
+    fs::File::create(&path).map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_file_points_the_hostname_at_127_0_1_1() {
+        let debian = "127.0.0.1\tlocalhost\n127.0.1.1\told-name\n::1\tlocalhost ip6-localhost\n";
+        assert_eq!(hosts_file(debian, "box"), "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost\n127.0.1.1\tbox\n");
+    }
+
+    #[test]
+    fn hosts_file_adds_localhost_when_missing() {
+        assert_eq!(hosts_file("", "box"), "127.0.0.1\tlocalhost\n127.0.1.1\tbox\n");
+        assert_eq!(
+            hosts_file("# static\n10.0.0.2 nas\n", "box"),
+            "127.0.0.1\tlocalhost\n# static\n10.0.0.2 nas\n127.0.1.1\tbox\n"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::process::Command;
 use std::path::{Path, PathBuf};
 use std::fs;
@@ -5,167 +6,294 @@ use std::time::{UNIX_EPOCH};
 use std::io::{self, Write};
 use nix::unistd::User;
 
-use std::os::unix::fs::FileTypeExt;
-use walkdir::WalkDir;
-use nix::unistd::geteuid;
-use nix::sched::{self, CloneFlags};
+use nix::sys::resource::{setrlimit, Resource};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{geteuid, Pid};
 use dir_size;
 
-use crate::profile::{read_current_profile, load_profiles, Profile, MemoryConfig::{self, Unlimited, Percent, Fixed}, apply_profile_cpu};
-use crate::helpers::{errln, BLUE, ESC, infoln, rooted, ONYX_DIR, BLUEB, set_nice, set_memory_limit, RED, YELLOW, DIM};
+use crate::profile::{read_current_profile, load_profiles, Profile, MemoryConfig::{self, Unlimited, Percent, Fixed}, apply_profile_cpu, apply_profile_io_sched};
+use crate::helpers::{errln, BLUE, ESC, infoln, ONYX_DIR, BLUEB, set_nice, set_memory_limit, RED, YELLOW, DIM};
 use crate::check_file_authority;
+use crate::backend::{GuestUser, Isolation, LaunchOpts, Net, Session, Target, EXIT_ONYX};
+use crate::manifest::{Bind, BoxManifest};
+use crate::cgroup::{Limits, SessionGroup};
 
-//=== mount guard ===//
-struct MountGuard {
-    mounts: Vec<PathBuf>,
-    merged: PathBuf,
-    is_overlay: bool,
-}
-
-impl MountGuard {
-    /// `root` = sys_path (ONYX_DIR/sys/<system_name>)
-    /// `uid`  = user id
-    /// `system_name` = e.g., "debian", "alpine"
-    fn new(root: &Path, uid: Option<&str>, system_name: &str) -> Result<Self, String> {
-        let mut is_overlay = false;
-        
-        let merged = if let Some(uid) = uid {
-            is_overlay = true;
-            
-            // NEW PATH LOGIC: ONYX_DIR/delta/<uid>/<system_name>/...
-            // this prevents different OSs from sharing the same 'upper' layer
-            let base = ONYX_DIR.join("delta").join(uid).join(system_name);
-            let upper = base.join("upper");
-            let work  = base.join("work");
-            let merged = base.join("merged");
-
-            for d in [&upper, &work, &merged] {
-                std::fs::create_dir_all(d)
-                    .map_err(|e| format!("failed to create {}: {}", d.display(), e))?;
-            }
-
-            let opts = format!(
-                "lowerdir={},upperdir={},workdir={}",
-                root.display(),
-                upper.display(),
-                work.display()
-            );
-            
-            run("mount", &["-t", "overlay", "overlay", "-o", &opts, merged.to_str().unwrap()])?;
+//=== box cmds ===//
+fn find_shell(root: &Path) -> String {
+    find_shell_in(&[root.to_path_buf()])
+}
 
-            merged
-        } else {
-            root.to_path_buf()
+/// like find_shell, but looks through every layer of a (derived) box
+fn find_shell_in(layers: &[PathBuf]) -> String {
+    let candidates = [
+        "usr/bin/zsh",
+        "bin/bash",
+        "bin/ash",
+        "bin/sh",
+        "usr/bin/bash",
+    ];
+
+    for s in candidates {
+        if layers.iter().any(|root| root.join(s).exists()) {
+            return format!("/{}", s);
+        }
+    }
+
+    "/bin/sh".to_string()
+}
+
+/// the shell from the box manifest if it exists in the rootfs, else autodetect
+fn box_shell(manifest: &BoxManifest, layers: &[PathBuf]) -> String {
+    if let Some(shell) = &manifest.shell
+        && layers.iter().any(|root| root.join(shell.trim_start_matches('/')).exists())
+    {
+        return shell.clone();
+    }
+    find_shell_in(layers)
+}
+
+/// the box's persistent binds plus any `--bind=` flags, checked up front
+fn collect_binds(manifest: &BoxManifest, flags: &[String]) -> Result<Vec<Bind>, String> {
+    let specs = manifest
+        .binds
+        .iter()
+        .map(|s| s.as_str())
+        .chain(flags.iter().filter_map(|a| a.strip_prefix("--bind=")));
+
+    let mut out: Vec<Bind> = Vec::new();
+    for spec in specs {
+        let Some(mut bind) = crate::manifest::parse_bind(spec) else {
+            return Err(format!("invalid bind '{}', expected <host>:<guest>[:ro]", spec));
         };
+        let host = fs::canonicalize(&bind.host).map_err(|e| format!("bind source {}: {}", bind.host, e))?;
+        bind.host = host.to_string_lossy().to_string();
 
-        let mut mounts = Vec::new();
+        // a later bind for the same guest path (e.g. from the command line) wins
+        out.retain(|b| b.guest != bind.guest);
+        out.push(bind);
+    }
+    Ok(out)
+}
 
-        // -- make / private to avoid mount leakage to host --
-        run("mount", &["--make-rprivate", "/"])?;
+/// launch flags that `exec` takes between the box name and the command
+const BOX_FLAGS: [&str; 11] = [
+    "--bind=", "--profile=", "--env=", "--env-file=", "--pass-env=", "--user=", "--workdir=", "--shell", "--backend=",
+    "--isolate=", "--net=",
+];
 
-        // bind mounts logic (proc, dev, sys)
-        let binds = vec![
-            ("proc", "proc", vec!["-t", "proc"]),
-            ("/dev", "dev", vec!["--bind"]),
-            ("/dev/pts", "dev/pts", vec!["--bind"]),
-            ("/sys", "sys", vec!["--bind"]),
-        ];
+/// flags ending in '=' take a value; the rest have to match exactly
+fn is_box_flag(arg: &str) -> bool {
+    BOX_FLAGS.iter().any(|f| if f.ends_with('=') { arg.starts_with(f) } else { arg == *f })
+}
 
-        for (src, dest_rel, args) in binds {
-            let dest = merged.join(dest_rel);
-            std::fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
-            
-            // convert to string once so we don't keep fighting the borrow checker
-            let dest_str = dest.to_str().ok_or("invalid path")?.to_string();
-            
-            let mut cmd_args = args.clone();
-            cmd_args.push(src);
-            cmd_args.push(&dest_str);
-            
-            run("mount", &cmd_args)?;
-            
-            // extra hardening for /sys and /dev using our string reference
-            if dest_rel == "sys" {
-                run("mount", &["-o", "remount,ro,bind", &dest_str])?;
-            }
-            if dest_rel == "dev" {
-                run("mount", &["--make-slave", &dest_str])?;
-            }
+/// a guest file as the session will see it (`layers` top to bottom)
+fn read_guest_file(layers: &[PathBuf], rel: &str) -> Option<String> {
+    crate::layer::find(layers, Path::new(rel)).and_then(|p| fs::read_to_string(p).ok())
+}
 
-            // NOW we move it. once it's in the vector, 'dest' is gone.
-            mounts.push(dest);
-        }
+/// resolve `--user=<name|uid|uid:gid>` against the guest's /etc/passwd
+fn resolve_guest_user(spec: &str, layers: &[PathBuf]) -> Result<GuestUser, String> {
+    let passwd = crate::manifest::parse_passwd(&read_guest_file(layers, "etc/passwd").unwrap_or_default());
 
-        Ok(Self { mounts, merged, is_overlay })
+    let (uid_part, gid_part) = match spec.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (spec, None),
+    };
+
+    let entry = match uid_part.parse::<u32>() {
+        Ok(uid) => passwd.iter().find(|e| e.uid == uid).cloned(),
+        Err(_) => Some(
+            passwd
+                .iter()
+                .find(|e| e.name == uid_part)
+                .cloned()
+                .ok_or_else(|| format!("no user '{}' in the box's /etc/passwd", uid_part))?,
+        ),
+    };
+
+    let uid = match &entry {
+        Some(e) => e.uid,
+        None => uid_part.parse::<u32>().map_err(|_| format!("invalid user '{}'", spec))?,
+    };
+    let gid = match gid_part {
+        Some(g) => g.parse::<u32>().map_err(|_| format!("invalid group in '{}'", spec))?,
+        None => entry.as_ref().map(|e| e.gid).unwrap_or(uid),
+    };
+
+    let groups = match &entry {
+        Some(e) => crate::manifest::guest_groups(&read_guest_file(layers, "etc/group").unwrap_or_default(), &e.name),
+        None => Vec::new(),
+    };
+
+    Ok(GuestUser {
+        uid,
+        gid,
+        name: entry.as_ref().map(|e| e.name.clone()),
+        home: entry.as_ref().map(|e| e.home.clone()).filter(|h| !h.is_empty()).unwrap_or_else(|| "/".to_string()),
+        shell: entry.map(|e| e.shell).filter(|s| !s.is_empty()),
+        groups,
+    })
+}
+
+/// gather binds, env, guest user, workdir, shell and isolation for a session on `box_name`
+fn launch_opts(manifest: &BoxManifest, layers: &[PathBuf], box_name: &str, flags: &[String]) -> Result<LaunchOpts, String> {
+    let binds = collect_binds(manifest, flags)?;
+
+    // passwd edits in the user's own delta count too
+    let upper = ONYX_DIR.join("delta").join(geteuid().as_raw().to_string()).join(box_name).join("upper");
+    let mut view = vec![upper];
+    view.extend(layers.iter().cloned());
+
+    let user_flag = flags.iter().find_map(|a| a.strip_prefix("--user="));
+    let guest = match user_flag {
+        Some(spec) => resolve_guest_user(spec, &view)?,
+        None => GuestUser::root(),
+    };
+
+    let workdir = match flags.iter().find_map(|a| a.strip_prefix("--workdir=")) {
+        Some(dir) if dir.starts_with('/') => dir.to_string(),
+        Some(dir) => return Err(format!("workdir '{}' must be an absolute path inside the box", dir)),
+        // a chosen user starts at home, like a login would
+        None if user_flag.is_some() && crate::layer::find(&view, Path::new(guest.home.trim_start_matches('/'))).is_some() => guest.home.clone(),
+        None => "/".to_string(),
+    };
+
+    let env = box_env(manifest, flags, &guest)?;
+    let shell = session_shell(manifest, &view, &guest);
+
+    let mut isolate = Isolation::default();
+    for list in flags.iter().filter_map(|a| a.strip_prefix("--isolate=")) {
+        isolate.add(list)?;
     }
-    fn root(&self) -> &Path {
-        &self.merged
+    if let Some(mode) = flags.iter().rev().find_map(|a| a.strip_prefix("--net=")) {
+        isolate.net = Net::parse(mode)?;
     }
-}
 
-impl Drop for MountGuard {
-    fn drop(&mut self) {
-        // 1. reverse unmount children (pts -> dev -> sys -> proc)
-        for m in self.mounts.iter().rev() {
-            let _ = Command::new("umount").arg("-l").arg(m).status();
-        }
+    let hostname = manifest.hostname.clone().unwrap_or_else(|| box_hostname(box_name));
 
-        // 2. unmount the overlay itself
-        if self.is_overlay {
-            let _ = Command::new("umount").arg("-l").arg(&self.merged).status();
-            // 3. clean up the MERGED mountpoint directory
-            let _ = std::fs::remove_dir(&self.merged);
-        }
+    Ok(LaunchOpts { binds, env, guest, workdir, shell, isolate, hostname })
+}
+
+/// the guest user's login shell if it's usable, else the box's shell
+fn session_shell(manifest: &BoxManifest, layers: &[PathBuf], guest: &GuestUser) -> String {
+    if let Some(shell) = &guest.shell
+        && !shell.ends_with("nologin")
+        && !shell.ends_with("false")
+        && layers.iter().any(|l| l.join(shell.trim_start_matches('/')).exists())
+    {
+        return shell.clone();
     }
+    box_shell(manifest, layers)
 }
 
-//=== box cmds ===//
-fn run(cmd: &str, args: &[&str]) -> Result<(), String> {
-    let status = Command::new(cmd)
-        .args(args)
-        .status()
-        .map_err(|e| format!("failed to run {}: {}", cmd, e))?;
+const GUEST_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
 
-    if !status.success() {
-        return Err(format!("command failed: {} {:?}", cmd, args));
+/// does host variable `name` match an allowlist entry ("LANG", or a prefix like "LC_*")?
+fn pass_env_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
     }
-    Ok(())
 }
 
-fn find_shell(root: &Path) -> String {
-    let candidates = [
-        "usr/bin/zsh",
-        "bin/bash",
-        "bin/ash",
-        "bin/sh",
-        "usr/bin/bash",
-    ];
+/// the guest's environment, lowest priority first: a clean base, allowlisted
+/// host variables, the box's own env, `--env-file=` files, then `--env=` flags
+fn box_env(manifest: &BoxManifest, flags: &[String], guest: &GuestUser) -> Result<BTreeMap<String, String>, String> {
+    let mut env = BTreeMap::new();
+    env.insert("HOME".to_string(), guest.home.clone());
+    if let Some(name) = &guest.name
+        && !guest.is_root()
+    {
+        env.insert("USER".to_string(), name.clone());
+        env.insert("LOGNAME".to_string(), name.clone());
+    }
+    env.insert("TERM".to_string(), std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()));
+    env.insert("PATH".to_string(), GUEST_PATH.to_string());
 
-    for s in candidates {
-        if root.join(s).exists() {
-            return format!("/{}", s);
+    let mut pass = manifest.pass_env.clone();
+    for list in flags.iter().filter_map(|a| a.strip_prefix("--pass-env=")) {
+        pass.extend(list.split(',').filter(|p| !p.is_empty()).map(|p| p.to_string()));
+    }
+    for (k, v) in std::env::vars() {
+        if pass.iter().any(|p| pass_env_matches(p, &k)) {
+            env.insert(k, v);
         }
     }
 
-    "/bin/sh".to_string()
+    env.extend(manifest.env.clone());
+
+    for path in flags.iter().filter_map(|a| a.strip_prefix("--env-file=")) {
+        let vars = crate::manifest::parse_env_file(Path::new(path)).map_err(|e| format!("env file {}: {}", path, e))?;
+        env.extend(vars);
+    }
+
+    for kv in flags.iter().filter_map(|a| a.strip_prefix("--env=")) {
+        let Some((k, v)) = kv.split_once('=') else {
+            return Err(format!("invalid env '{}', expected K=V", kv));
+        };
+        env.insert(k.to_string(), v.to_string());
+    }
+
+    Ok(env)
 }
 
-/// try to create a mount namespace. returns Ok(()) if success.
-/// on failure, returns Err with explanation.
-fn try_unshare_mount_ns() -> Result<(), String> {
-    match sched::unshare(CloneFlags::CLONE_NEWNS) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("unshare(CLONE_NEWNS) failed: {}", e)),
+/// rlimits and oom_score_adj that hold the same with or without cgroups.
+/// onyx sets them on itself and everything in the box inherits them.
+fn apply_process_limits(prof: &Profile) {
+    for (resource, value, what) in [
+        (Resource::RLIMIT_NOFILE, prof.files, "open files"),
+        (Resource::RLIMIT_CPU, prof.cpu_seconds, "cpu time"),
+    ] {
+        if let Some(value) = value
+            && let Err(e) = setrlimit(resource, value, value)
+        {
+            errln("box", &format!("failed to limit {}: {}", what, e));
+        }
     }
+
+    if let Some(adj) = prof.oom_score_adj
+        && let Err(e) = fs::write("/proc/self/oom_score_adj", adj.to_string())
+    {
+        // lowering it below the current value needs CAP_SYS_RESOURCE
+        errln("box", &format!("failed to set oom_score_adj to {}: {}", adj, e));
+    }
+}
+
+/// kill whatever onyx started once the session has run for `secs` seconds:
+/// SIGTERM first, then SIGKILL for anything still around a few seconds later
+fn start_session_timeout(secs: u64) {
+    std::thread::spawn(move || {
+        // signals for onyx must reach the main thread, which forwards them into the box
+        let _ = nix::sys::signal::SigSet::all().thread_block();
+        std::thread::sleep(std::time::Duration::from_secs(secs));
+
+        errln("box", &format!("session hit the profile's {}s timeout; stopping it", secs));
+        for (sig, grace) in [(Signal::SIGTERM, 5), (Signal::SIGKILL, 0)] {
+            for pid in crate::session::descendants(std::process::id()) {
+                let _ = kill(Pid::from_raw(pid as i32), sig);
+            }
+            std::thread::sleep(std::time::Duration::from_secs(grace));
+        }
+    });
 }
 
-fn limit_box(profile: String) {
+/// apply a performance profile to this process, and with it the session.
+/// memory and cpu go into a cgroup v2 group when the host delegates one
+/// (kept alive by the returned guard), else memory falls back to RLIMIT_AS.
+fn limit_box(profile: String, box_name: &str, guest_uid: u32) -> Option<SessionGroup> {
     let backup = Profile {
         name: "backup".to_string(),
         description: Some("Temporary backup profile".to_string()),
         nice: 0,
+        procs: None,
+        files: None,
+        cpu_seconds: None,
+        timeout: None,
+        oom_score_adj: None,
         memory: MemoryConfig::Unlimited,
         cpu: None,
+        io: None,
+        sched: None,
     };
     let mut binding;
     let prof;
@@ -182,17 +310,62 @@ fn limit_box(profile: String) {
         prof = binding.get(&profile).unwrap_or(&backup);
     }
 
-    apply_profile_cpu(prof);
+    let cores = apply_profile_cpu(prof);
 
     let _ = set_nice(prof.nice);
-    match prof.memory {
-        Unlimited => {}
-        Percent{value} => {
-            let limit = crate::doctor::get_mem().1 * value as u64 / 100;
-            let _ = set_memory_limit(limit * 1024 * 1024);
+    apply_profile_io_sched(prof);
+    apply_process_limits(prof);
+    if let Some(secs) = prof.timeout {
+        start_session_timeout(secs);
+    }
+    let memory = match prof.memory {
+        Unlimited => None,
+        // MemTotal is in kB
+        Percent{value} => Some(crate::doctor::get_mem().1 * 1024 * value as u64 / 100),
+        Fixed{mb} => Some(mb * 1024 * 1024),
+    };
+
+    let limits = Limits {
+        memory,
+        cpu: prof.cpu.as_ref().and_then(|c| c.quota).map(|q| q * cores.len() as u32),
+        pids: prof.procs,
+        io_weight: prof.io.as_ref().and_then(|io| io.weight),
+        io_read: prof.io.as_ref().and_then(|io| io.read_mbps).map(|mb| mb * 1024 * 1024),
+        io_write: prof.io.as_ref().and_then(|io| io.write_mbps).map(|mb| mb * 1024 * 1024),
+    };
+    if limits.is_empty() {
+        return None;
+    }
+
+    match SessionGroup::enter(box_name, &limits) {
+        Ok(group) => {
+            infoln("box", &format!("limits: cgroup v2 ({})", group.path().display()));
+            Some(group)
         }
-        Fixed{mb} => {
-            let _ = set_memory_limit(mb * 1024 * 1024);
+        Err(why) => {
+            // RLIMIT_AS caps address space rather than memory use, so it's only the fallback
+            infoln("box", &format!("limits: rlimit fallback ({})", why));
+            if let Some(bytes) = memory {
+                let _ = set_memory_limit(bytes);
+            }
+            // RLIMIT_NPROC counts every process the guest's uid has on the host, so it
+            // only stands in when that isn't the caller: a root session with --user=
+            if let Some(procs) = limits.pids {
+                if crate::helpers::rooted() && guest_uid != 0 {
+                    if let Err(e) = setrlimit(Resource::RLIMIT_NPROC, procs, procs) {
+                        errln("box", &format!("failed to limit processes: {}", e));
+                    }
+                } else {
+                    errln("box", "the profile's process limit needs cgroup v2 (or root with --user=); skipping it");
+                }
+            }
+            if limits.cpu.is_some() {
+                errln("box", "the profile's cpu quota needs cgroup v2; only the core pinning applies");
+            }
+            if limits.io_weight.is_some() || limits.io_read.is_some() || limits.io_write.is_some() {
+                errln("box", "the profile's io weight and caps need cgroup v2; only the io priority applies");
+            }
+            None
         }
     }
 }
@@ -217,26 +390,175 @@ pub fn cmd(args: Vec<String>) {
             }
         }
 
+        "rename" => {
+            if args.len() < 5 {
+                errln("box", "usage: onyx box rename <old> <new>");
+                std::process::exit(1);
+            }
+            if let Err(e) = rename_box(&args[3], &args[4]) {
+                errln("box", &format!("rename failed for '{}': {}", args[3], e));
+                std::process::exit(1);
+            }
+        }
+        "duplicate" => {
+            let positional: Vec<&String> = args.iter().skip(3).filter(|a| !a.starts_with("--")).collect();
+            if positional.len() < 2 {
+                errln("box", "usage: onyx box duplicate <src> <dst> [--with-delta=<user>]");
+                std::process::exit(1);
+            }
+            let delta_user = args.iter().find_map(|a| a.strip_prefix("--with-delta="));
+
+            if let Err(e) = duplicate_box(positional[0], positional[1], delta_user) {
+                errln("box", &format!("duplicate failed for '{}': {}", positional[0], e));
+                std::process::exit(1);
+            }
+        }
+
         "create" => {
+            if let Some(parent) = args.iter().find_map(|a| a.strip_prefix("--from=")) {
+                if args.len() < 5 {
+                    errln("box", "usage: onyx box create <name> --from=<parent>");
+                    std::process::exit(1);
+                }
+                let name = &args[3];
+                if let Err(e) = create_derived_box(name, parent) {
+                    errln("box", &format!("creation failed for '{}': {}", name, e));
+                    std::process::exit(1);
+                }
+                return;
+            }
+
             if args.len() < 5 {
-                errln("box", "usage: onyx box create <name> <rootfs-folder> [--move=true]");
+                errln("box", "usage: onyx box create <name> <rootfs-folder> [--move=true] [--copy=full|reflink|hardlink]");
                 std::process::exit(1);
             }
             let name = &args[3];
             let source = Path::new(&args[4]);
             
             let move_flag = args.iter().any(|arg| arg.to_lowercase() == "--move=true");
-
-            if let Err(e) = create_box(name, source, move_flag) {
+            let method = match args.iter().find_map(|a| a.strip_prefix("--copy=")) {
+                None => crate::copy::Method::Full,
+                Some(m) => match crate::copy::Method::from_name(m) {
+                    Some(method) => method,
+                    None => {
+                        errln("box", &format!("unknown copy mode '{}' (use full, reflink or hardlink)", m));
+                        std::process::exit(1);
+                    }
+                },
+            };
+
+            if let Err(e) = create_box(name, source, move_flag, method) {
                 errln("box", &format!("creation failed for '{}': {}", name, e));
                 std::process::exit(1);
             }
         }
+        "import" => {
+            if args.len() < 5 {
+                errln("box", "usage: onyx box import <name> <archive>");
+                std::process::exit(1);
+            }
+            let name = &args[3];
+            let archive = Path::new(&args[4]);
+
+            if let Err(e) = import_box(name, archive) {
+                errln("box", &format!("import failed for '{}': {}", name, e));
+                std::process::exit(1);
+            }
+        }
+        "export" => {
+            let positional: Vec<&String> = args.iter().skip(3).filter(|a| !a.starts_with("--")).collect();
+            if positional.len() < 2 {
+                errln("box", "usage: onyx box export <name> <out.tar.zst> [--with-delta=<user>]");
+                std::process::exit(1);
+            }
+            let name = positional[0];
+            let out = Path::new(positional[1]);
+
+            let delta_user = args.iter().find_map(|a| a.strip_prefix("--with-delta=")).map(|s| s.to_string());
+
+            if let Err(e) = export_box(name, out, delta_user.as_deref()) {
+                errln("box", &format!("export failed for '{}': {}", name, e));
+                std::process::exit(1);
+            }
+        }
+        "snapshot" | "snapshots" | "rollback" => {
+            let positional: Vec<&String> = args.iter().skip(3).filter(|a| !a.starts_with("--")).collect();
+            let user = args.iter().find_map(|a| a.strip_prefix("--user=")).unwrap_or("self");
+
+            let needs_tag = args[2] != "snapshots";
+            if positional.is_empty() || (needs_tag && positional.len() < 2) {
+                errln("box", &format!("usage: onyx box {} <name>{} [--user=<name>]", args[2], if needs_tag { " <tag>" } else { "" }));
+                std::process::exit(1);
+            }
+            let name = positional[0];
+
+            if !ONYX_DIR.join("sys").join(name).exists() {
+                errln("box", &format!("box '{}' does not exist", name));
+                std::process::exit(1);
+            }
+
+            let result = resolve_uid(user).and_then(|uid| match args[2].as_str() {
+                "snapshot" => crate::snapshot::create(&uid, name, positional[1]),
+                "rollback" => crate::snapshot::rollback(&uid, name, positional[1]),
+                _ => crate::snapshot::list(&uid, name),
+            });
+
+            if let Err(e) = result {
+                errln("box", &format!("{} failed for '{}': {}", args[2], name, e));
+                std::process::exit(1);
+            }
+        }
+        "diff" => {
+            let positional: Vec<&String> = args.iter().skip(3).filter(|a| !a.starts_with("--")).collect();
+            if positional.len() < 2 {
+                errln("box", "usage: onyx box diff <user> <box> [--summary] [--json] [--content]");
+                std::process::exit(1);
+            }
+            let (user, name) = (positional[0].as_str(), positional[1].as_str());
+
+            let opts = crate::diff::DiffOpts {
+                json: args.iter().any(|a| a == "--json"),
+                summary: args.iter().any(|a| a == "--summary"),
+                content: args.iter().any(|a| a == "--content"),
+            };
+
+            if let Err(e) = resolve_uid(user).and_then(|uid| crate::diff::show(user, &uid, name, &opts)) {
+                errln("box", &format!("diff failed for '{}': {}", name, e));
+                std::process::exit(1);
+            }
+        }
+        "reset" => {
+            let positional: Vec<&String> = args.iter().skip(3).filter(|a| !a.starts_with("--")).collect();
+            if positional.is_empty() {
+                errln("box", "usage: onyx box reset <box> [--user=<name>] [--path=PATH]...");
+                std::process::exit(1);
+            }
+            let user = args.iter().find_map(|a| a.strip_prefix("--user=")).unwrap_or("self");
+            let paths: Vec<String> = args.iter().filter_map(|a| a.strip_prefix("--path=")).map(|s| s.to_string()).collect();
+
+            if let Err(e) = reset_delta(user, positional[0], &paths) {
+                errln("box", &format!("reset failed for '{}': {}", positional[0], e));
+                std::process::exit(1);
+            }
+        }
+        "config" => {
+            if args.len() < 4 {
+                errln("box", "usage: onyx box config <name> [--shell=PATH] [--profile=NAME] [--hostname=NAME] [--env=K=V] [--unset-env=K] [--env-file=PATH] [--pass-env=NAME,...] [--unpass-env=NAME] [--bind=HOST:GUEST[:ro]] [--unbind=GUEST]");
+                std::process::exit(1);
+            }
+            if let Err(e) = config_box(&args[3], &args[4..]) {
+                errln("box", &format!("failed to configure '{}': {}", args[3], e));
+                std::process::exit(1);
+            }
+        }
         "open" => {
             open(args);
         }
         "exec" => {
-            exec(args);
+            let code = exec(args);
+            if code != 0 {
+                std::process::exit(code);
+            }
         }
         "list" => {
             list();
@@ -244,9 +566,26 @@ pub fn cmd(args: Vec<String>) {
         "apply-delta" => {
             let perms = check_file_authority(&ONYX_DIR).unwrap();
             if perms.0 == true || perms.1 == true {
-                let name = args.get(3).expect("error: missing <name>");
-                let system = args.get(4).expect("error: missing <system>");
-                apply_delta(name, system).expect("failed to execute merge");
+                let positional: Vec<&String> = args.iter().skip(3).filter(|a| !a.starts_with("--")).collect();
+                if positional.len() < 2 {
+                    errln("box", "usage: onyx box apply-delta <user> <box> [--dry-run|--resume|--rollback]");
+                    std::process::exit(1);
+                }
+
+                let mode = if args.iter().any(|a| a == "--dry-run") {
+                    crate::commit::Mode::DryRun
+                } else if args.iter().any(|a| a == "--resume") {
+                    crate::commit::Mode::Resume
+                } else if args.iter().any(|a| a == "--rollback") {
+                    crate::commit::Mode::Rollback
+                } else {
+                    crate::commit::Mode::Merge
+                };
+
+                if let Err(e) = apply_delta(positional[0], positional[1], mode) {
+                    errln("box", &format!("merge failed: {}", e));
+                    std::process::exit(1);
+                }
             } else {
                 errln("box", "this user cannot edit the rootfs.");
             }
@@ -257,7 +596,9 @@ pub fn cmd(args: Vec<String>) {
     }
 }
 
-fn apply_delta(username: &str, system_name: &str) -> io::Result<()> {
+/// turns a username (or "self", or a raw uid) into the uid string
+/// used for delta paths: ONYX_DIR/delta/<uid>/<box>
+fn resolve_uid(username: &str) -> io::Result<String> {
     // 1. get current real UID as a baseline
     let current_uid = nix::unistd::getuid().as_raw().to_string();
 
@@ -281,7 +622,15 @@ fn apply_delta(username: &str, system_name: &str) -> io::Result<()> {
             return Err(io::Error::new(io::ErrorKind::NotFound, "user lookup failed completely"));
         }
     };
-    
+
+    Ok(uid)
+}
+
+fn apply_delta(username: &str, system_name: &str, mode: crate::commit::Mode) -> io::Result<()> {
+    use crate::commit::Mode;
+
+    let uid = resolve_uid(username)?;
+
     // 2. build paths based on system
     // assuming your structure is: onyx/sys/<system_name>
     let brick_path = ONYX_DIR.join("sys").join(system_name);
@@ -298,71 +647,207 @@ fn apply_delta(username: &str, system_name: &str) -> io::Result<()> {
         return Ok(());
     }
 
-    // 4. the "no turning back" confirmation
-    println!("{YELLOW}⚠ PERMANENT MERGE:{ESC} user '{}' -> system '{}'", username, system_name);
-    print!("{BLUE}[box]{ESC} this will overwrite files in {}. confirm? [y/N]: ", system_name);
+    // merging underneath a live overlay would corrupt both sides
+    let pids = crate::session::active(&uid, system_name);
+    if !pids.is_empty() && mode != Mode::DryRun {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("box '{}' has running sessions for this delta (pids {:?}); exit them first", system_name, pids),
+        ));
+    }
+
+    if mode == Mode::Merge {
+        if crate::commit::pending(&uid, system_name) {
+            errln("box", "an earlier merge was interrupted; rerun with --resume or --rollback");
+            return Ok(());
+        }
+
+        // 4. the "no turning back" confirmation
+        println!("{YELLOW}⚠ PERMANENT MERGE:{ESC} user '{}' -> system '{}'", username, system_name);
+        print!("{BLUE}[box]{ESC} this will overwrite files in {}. confirm? [y/N]: ", system_name);
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        if input.trim().to_lowercase() != "y" {
+            infoln("box", "merge aborted by user.");
+            return Ok(());
+        }
+
+        infoln("box", format!("committing delta to {}...", system_name).as_str());
+    }
+
+    let result = crate::commit::commit(&uid, system_name, mode);
+
+    // merged files keep the ids they had in the delta
+    if mode != Mode::DryRun
+        && let Some(mut manifest) = crate::manifest::load(system_name)
+    {
+        manifest.owner = Some(crate::manifest::detect_owner(&brick_path));
+        crate::manifest::save(&manifest)?;
+    }
+
+    if let Err(e) = result {
+        if mode != Mode::DryRun {
+            errln("box", "merge stopped; rerun with --resume to finish it or --rollback to undo it.");
+        }
+        return Err(e);
+    }
+
+    if matches!(mode, Mode::Merge | Mode::Resume) {
+        infoln("box", "delta flushed. system is now updated.");
+    }
+
+    Ok(())
+}
+
+/// is something mounted at `path`? (checked against /proc/self/mountinfo)
+fn is_mounted(path: &Path) -> bool {
+    let Ok(info) = fs::read_to_string("/proc/self/mountinfo") else {
+        return false;
+    };
+    let want = path.to_string_lossy().replace(' ', "\\040");
+    info.lines().any(|l| l.split_whitespace().nth(4) == Some(want.as_str()))
+}
+
+/// remove a dir tree even when parts of it are mode 000, like the
+/// `work/work` dir overlayfs leaves behind
+fn force_remove_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = match fs::symlink_metadata(dir) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !meta.is_dir() {
+        return fs::remove_file(dir);
+    }
+
+    let _ = fs::set_permissions(dir, fs::Permissions::from_mode(0o700));
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            force_remove_dir(&entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    fs::remove_dir(dir)
+}
+
+/// throw away a user's delta (or just the parts of it under `paths`)
+fn reset_delta(username: &str, system_name: &str, paths: &[String]) -> io::Result<()> {
+    let uid = resolve_uid(username)?;
+    let delta = ONYX_DIR.join("delta").join(&uid).join(system_name);
+    let upper = delta.join("upper");
+    let work = delta.join("work");
+    let merged = delta.join("merged");
+
+    if !ONYX_DIR.join("sys").join(system_name).exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("box '{}' does not exist", system_name)));
+    }
+    if !delta.exists() {
+        infoln("box", &format!("uid {} has no delta for '{}'; nothing to reset", uid, system_name));
+        return Ok(());
+    }
+
+    let pids = crate::session::active(&uid, system_name);
+    if !pids.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("box '{}' has running sessions for this delta (pids {:?}); exit them first", system_name, pids),
+        ));
+    }
+
+    // the journal is the only way back from a half-done merge
+    if crate::commit::pending(&uid, system_name) {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            "an interrupted merge is pending; run apply-delta with --resume or --rollback first",
+        ));
+    }
+
+    // a crashed session can leave the overlay mounted; deleting through
+    // it would eat the box itself
+    if is_mounted(&merged) {
+        let _ = Command::new("umount").arg("-l").arg(&merged).status();
+        let _ = Command::new("fusermount").arg("-u").arg(&merged).status();
+        if is_mounted(&merged) {
+            return Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                format!("{} is still mounted; unmount it first", merged.display()),
+            ));
+        }
+    }
+
+    let what = if paths.is_empty() { "all changes".to_string() } else { paths.join(", ") };
+    println!("{YELLOW}⚠ DISCARD DELTA:{ESC} uid {} on system '{}': {}", uid, system_name, what);
+    print!("{BLUE}[box]{ESC} this cannot be undone. confirm? [y/N]: ");
     io::stdout().flush()?;
-    
+
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
 
     if input.trim().to_lowercase() != "y" {
-        infoln("box", "merge aborted by user.");
+        infoln("box", "reset aborted by user.");
         return Ok(());
     }
 
-    infoln("box", format!("committing delta to {}...", system_name).as_str());
-
-    // 1. handle removals (whiteouts) FIRST
-    // we do this before rsync so we don't copy whiteout devices into the brick
-    for entry in WalkDir::new(&delta_path).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        let metadata = entry.metadata()?;
+    if paths.is_empty() {
+        force_remove_dir(&upper)?;
+    } else {
+        for p in paths {
+            let rel = Path::new(p.trim_start_matches('/'));
+            if rel.as_os_str().is_empty() || rel.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("bad path '{}'", p)));
+            }
 
-        // fuse-overlayfs uses character devices with 0/0 as whiteouts
-        if metadata.file_type().is_char_device() {
-            let relative_path = path.strip_prefix(&delta_path).map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, "path prefix mismatch")
-            })?;
-            let target_in_brick = brick_path.join(relative_path);
-            
-            if target_in_brick.exists() {
-                println!("{DIM}[box] dbg:{ESC} removing whiteout: {}", target_in_brick.display());
-                let target_meta = fs::symlink_metadata(&target_in_brick)?;
-                if target_meta.is_dir() {
-                    fs::remove_dir_all(&target_in_brick)?;
-                } else {
-                    fs::remove_file(&target_in_brick)?;
+            // a deleted/replaced or opaque parent covers this path as a whole
+            for anc in rel.ancestors().skip(1).filter(|a| !a.as_os_str().is_empty()) {
+                let dir = upper.join(anc);
+                let Ok(m) = fs::symlink_metadata(&dir) else {
+                    break;
+                };
+                if !m.is_dir() || crate::layer::is_opaque(&dir) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("/{} is replaced as a whole; reset that path instead", anc.display()),
+                    ));
                 }
             }
-        }
-    }
 
-    // 2. sync additions and modifications
-    let status = Command::new("rsync")
-        .arg("-a")
-        .arg("-v")
-        .arg("--ignore-times")
-        .arg(format!("{}/", delta_path.display()))
-        .arg(&brick_path)
-        .status()?;
+            let target = upper.join(rel);
+            let mut victims = vec![target.clone()];
+            if let Some(name) = rel.file_name() {
+                victims.push(target.with_file_name(format!("{}{}", crate::layer::WHITEOUT_PREFIX, name.to_string_lossy())));
+            }
 
-    if status.success() {
-        infoln("box", "merge complete. cleaning up delta folder...");
-        
-        // nuke the whole system-specific delta dir (upper + work)
-        // delta_path is .../delta/UID/SYSTEM/upper -> parent is .../delta/UID/SYSTEM/
-        if let Some(system_delta_root) = delta_path.parent() {
-            if let Err(e) = fs::remove_dir_all(system_delta_root) {
-                errln("box", format!("failed to nuke delta dir: {}", e).as_str());
+            let mut found = false;
+            for v in victims {
+                if v.symlink_metadata().is_ok() {
+                    force_remove_dir(&v)?;
+                    found = true;
+                }
+            }
+            if !found {
+                infoln("box", &format!("no changes under {}", p));
             }
         }
-        
-        infoln("box", "delta flushed. system is now updated.");
-    } else {
-        errln("box", "rsync failed! brick might be in a partial state.");
     }
 
+    // the workdir is scratch space tied to the upper; always start it fresh
+    force_remove_dir(&work)?;
+    if merged.exists() {
+        fs::remove_dir(&merged)?;
+    }
+    if paths.is_empty() {
+        let _ = force_remove_dir(&delta.join("sessions"));
+        let _ = fs::remove_dir(&delta);
+    }
+
+    infoln("box", &format!("delta of '{}' for uid {} reset", system_name, uid));
     Ok(())
 }
 
@@ -402,6 +887,20 @@ fn list() {
                         .unwrap_or_else(|| "unknown".into());
 
                     println!("{BLUEB}{}:{ESC}", name);
+                    if let Some(m) = crate::manifest::load(&name) {
+                        println!("    {BLUE}[distro]{ESC} {}", m.distro.as_deref().unwrap_or("unknown"));
+                        println!("    {BLUE}[arch]{ESC} {}", m.arch.as_deref().unwrap_or("unknown"));
+                        println!("    {BLUE}[source]{ESC} {}", m.source);
+                        if let Some(p) = &m.parent {
+                            println!("    {BLUE}[parent]{ESC} {}", p);
+                        }
+                        println!("    {BLUE}[created]{ESC} {}", m.created);
+                        if let Some(p) = &m.profile {
+                            println!("    {BLUE}[profile]{ESC} {}", p);
+                        }
+                    } else {
+                        println!("    {DIM}[manifest] none (made before box.toml existed){ESC}");
+                    }
                     println!("    {BLUE}[size]{ESC} {}", size);
                     println!("    {BLUE}[modified]{ESC} {}", modified);
                 }
@@ -413,368 +912,130 @@ fn list() {
     }
 }
 
-// helper to keep the main block clean
-fn run_proot_session(root_path: &Path) {
-    let proot_bin = ONYX_DIR.join("bin/proot");
-    let shell = find_shell(root_path);
-    
-    Command::new(proot_bin)
-        .env_clear() // kill everything termux gave us
-        .env("HOME", "/root")
-        .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-        .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-        .env("PROOT_TMP_DIR", ONYX_DIR.join("tmp"))
-        .arg("-r").arg(root_path)
-        .arg("-0")
-        .arg("-b").arg("/dev").arg("-b").arg("/proc").arg("-b").arg("/sys")
-        .arg("--link2symlink")
-        .arg("-w").arg("/")
-        .arg(shell)
-        .status()
-        .expect("failed to run proot");
-}
-
-fn run_standalone_proot(sys_path: &Path) {
-    // on android, sys_path should be a writable copy of the rootfs
-    run_proot_session(sys_path);
-}
-
-fn exec(args: Vec<String>) {
-    if args.len() < 4 {
-        errln("box", "no system provided to exec");
-        return;
-    }
-
-    let sys_path = ONYX_DIR.join("sys").join(&args[3]);
-
-    if !sys_path.exists() {
-        errln("box", "system not found");
-        return;
-    }
-
-    let command = &args[4..];
-
-    if command.len() <= 0 {
-        errln("box", "no command provided to exec");
-        return;
-    }
-
-    let strcommand = command.join(" ");
-
+/// the profile a session runs under: --profile=, then the box's own, then the global one
+fn apply_session_profile(manifest: &BoxManifest, box_name: &str, flags: &[String], guest: &GuestUser) -> Option<SessionGroup> {
     let mut prof = String::new();
-    for arg in args.clone() {
+    for arg in flags {
         if let Some(profile) = arg.strip_prefix("--profile=") {
             prof = profile.to_string();
         }
     }
 
+    // the box's own default profile beats the global one
+    if prof.is_empty() && let Some(p) = &manifest.profile {
+        prof = p.clone();
+    }
+
     if prof.len() > 0 {
-        limit_box(prof);
+        limit_box(prof, box_name, guest.uid)
     } else {
         prof = read_current_profile().unwrap_or("__backup__".to_string());
         if prof.len() > 0 {
-            limit_box(prof);
+            limit_box(prof, box_name, guest.uid)
         } else {
-            limit_box("__backup__".to_string());
-        }
-    }
-
-    if !rooted() {
-        let is_android = std::env::var("PREFIX").map(|s| s.contains("com.termux")).unwrap_or(false);
-        let has_fuse = std::path::Path::new("/dev/fuse").metadata().is_ok();
-
-        // define paths
-        let uid_str = geteuid().as_raw().to_string();
-        let delta_dir = ONYX_DIR.join("delta").join(&uid_str).join(&args[3]);
-        let upper = delta_dir.join("upper");
-        let work = delta_dir.join("work");
-        let merged = delta_dir.join("merged");
-
-        // we only attempt the fuse dance if we aren't on android OR if /dev/fuse is miraculously open
-        let use_overlay = !is_android || has_fuse;
-
-        if use_overlay {
-            infoln("box", "launching namespaced session with proot...");
-
-            for dir in [&upper, &work, &merged] {
-                fs::create_dir_all(dir).unwrap();
-            }
-
-            let fuse_bin = ONYX_DIR.join("bin/fuse-overlayfs");
-            let proot_bin = ONYX_DIR.join("bin/proot");
-            let shell = "/bin/bash"; // or use your find_shell logic
-
-            // we build one giant command string that unshare executes
-            // 1. mount the fuse layer
-            // 2. run proot pointing to the newly merged layer
-            let chain_cmd = format!(
-                "{} -f -o lowerdir={},upperdir={},workdir={},squash_to_root {} & sleep 1 && {} -r {} -0 -b /dev -b /proc -b /sys --link2symlink -w / {} -c {}",
-                fuse_bin.display(),
-                sys_path.display(),
-                upper.display(),
-                work.display(),
-                merged.display(),
-                proot_bin.display(),
-                merged.display(),
-                shell,
-                strcommand
-            );
-
-            let status = Command::new("unshare")
-                .args(&["-U", "-r", "-m", "bash", "-c", &chain_cmd])
-                .status()
-                .expect("failed to execute namespaced chain");
-
-            if !status.success() {
-                errln("box", "session failed, falling back...");
-                let proot_bin = ONYX_DIR.join("bin/proot");
-                let shell = find_shell(&sys_path);
-                
-                Command::new(proot_bin)
-                    .env_clear() // kill everything termux gave us
-                    .env("HOME", "/root")
-                    .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-                    .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-                    .env("PROOT_TMP_DIR", ONYX_DIR.join("tmp"))
-                    .arg("-r").arg(&sys_path)
-                    .arg("-0")
-                    .arg("-b").arg("/dev").arg("-b").arg("/proc").arg("-b").arg("/sys")
-                    .arg("--link2symlink")
-                    .arg("-w").arg("/")
-                    .arg(shell)
-                    .status()
-                    .expect("failed to run proot");
-            }
-        } else {
-            infoln("box", "android detected: using standalone mode (no delta)");
-            let proot_bin = ONYX_DIR.join("bin/proot");
-            let shell = find_shell(&sys_path);
-            
-            Command::new(proot_bin)
-                .env_clear() // kill everything termux gave us
-                .env("HOME", "/root")
-                .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-                .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-                .env("PROOT_TMP_DIR", ONYX_DIR.join("tmp"))
-                .arg("-r").arg(&sys_path)
-                .arg("-0")
-                .arg("-b").arg("/dev").arg("-b").arg("/proc").arg("-b").arg("/sys")
-                .arg("--link2symlink")
-                .arg("-w").arg("/")
-                .arg(shell)
-                .status()
-                .expect("failed to run proot");
+            limit_box("__backup__".to_string(), box_name, guest.uid)
         }
-        return;
     }
+}
 
-    infoln("box", "running as root user with chroot");
+/// everything open and exec do before handing the box over to a backend.
+/// the session lock marks the delta as in use and the cgroup holds the
+/// profile's limits, both until they're dropped.
+type SessionGuards = (crate::session::SessionLock, Option<SessionGroup>);
 
-    // try to unshare mount namespace first
-    match try_unshare_mount_ns() {
-        Ok(_) => infoln("box", "entered new mount namespace (isolation enabled)"),
-        Err(e) => {
-            // fallback: make mounts private on the host (best-effort)
-            errln("box", &format!("couldn't create mount namespace: {}. falling back to private mounts.", e));
-            if let Err(e2) = run("mount", &["--make-rprivate", "/"]) {
-                errln("box", &format!("failed to make / rprivate: {}", e2));
-                errln("box", "refusing to proceed without isolation");
-                return;
-            }
-        }
+fn prepare_session(name: &str, flags: &[String], target: Target) -> Result<(Session, SessionGuards), String> {
+    let sys_path = ONYX_DIR.join("sys").join(name);
+    if !sys_path.exists() {
+        return Err("system not found".to_string());
     }
 
-    // RAII mount guard
-    let guard = match MountGuard::new(&sys_path, Some(&geteuid().to_string()), &args[3]) {
-        Ok(m) => m,
-        Err(e) => {
-            errln("box", &e);
-            return;
-        }
-    };
+    let manifest = crate::manifest::load(name).unwrap_or_default();
+    let layers = crate::manifest::layers(name);
+    let opts = launch_opts(&manifest, &layers, name, flags)?;
 
-    let shell = find_shell(&sys_path);
-    infoln("box", &format!("executing box command: {}", strcommand));
-    
-    match Command::new("chroot")
-    .env_clear() // kill everything termux gave us
-    .env("HOME", "/root")
-    .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-    .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-    .env("PROOT_TMP_DIR", ONYX_DIR.join("tmp"))
-    .arg(guard.root())       // chroot root = merged overlay
-    .arg(shell)              // run the shell
-    .arg("-c")               // execute a single command
-    .arg(strcommand)         // the command to run
-    .env_remove("LD_PRELOAD")
-    .status()
-    {
-        Ok(_) => {}, // command finished, ignore exit code
-        Err(e) => errln("box", &format!("chroot failed: {}", e)),
-    }
+    let lock = crate::session::SessionLock::acquire(&geteuid().as_raw().to_string(), name)
+        .map_err(|e| format!("failed to register session: {}", e))?;
 
-    // when function exits, MountGuard is dropped and unmounts occur inside
-    infoln("box", "unmounting...");
-}
+    let group = apply_session_profile(&manifest, name, flags, &opts.guest);
 
-fn open(args: Vec<String>) {
-    if args.len() < 4 {
-        errln("box", "no system provided to open");
-        return;
-    }
+    Ok((Session { name: name.to_string(), sys_path, layers, opts, target }, (lock, group)))
+}
 
-    let sys_path = ONYX_DIR.join("sys").join(&args[3]);
+fn backend_flag(flags: &[String]) -> &str {
+    flags.iter().find_map(|a| a.strip_prefix("--backend=")).unwrap_or("auto")
+}
 
-    if !sys_path.exists() {
-        errln("box", "system not found");
-        return;
+/// run a single command in a box. returns the status `onyx` should exit with.
+fn exec(args: Vec<String>) -> i32 {
+    if args.len() < 4 {
+        errln("box", "no system provided to exec");
+        return EXIT_ONYX;
     }
 
-    let mut prof = String::new();
-    for arg in args.clone() {
-        if let Some(profile) = arg.strip_prefix("--profile=") {
-            prof = profile.to_string();
+    // onyx's own flags go between the box name and the command; `--` ends them
+    let nflags = args[4..].iter().take_while(|a| *a != "--" && is_box_flag(a)).count();
+    let flags = &args[4..4 + nflags];
+    let command = match args.get(4 + nflags) {
+        Some(sep) if sep == "--" => &args[5 + nflags..],
+        Some(flag) if flag.starts_with("--") => {
+            errln("box", &format!("unknown flag '{}'; put the command after '--' if it's meant for the box", flag));
+            return EXIT_ONYX;
         }
-    }
+        _ => &args[4 + nflags..],
+    };
 
-    if prof.len() > 0 {
-        limit_box(prof);
-    } else {
-        prof = read_current_profile().unwrap_or("__backup__".to_string());
-        if prof.len() > 0 {
-            limit_box(prof);
-        } else {
-            limit_box("__backup__".to_string());
-        }
-    }
-
-    if !rooted() {
-        let is_android = std::env::var("PREFIX").map(|s| s.contains("com.termux")).unwrap_or(false);
-        let has_fuse = std::path::Path::new("/dev/fuse").metadata().is_ok();
-
-        // define paths
-        let uid_str = geteuid().as_raw().to_string();
-        let delta_dir = ONYX_DIR.join("delta").join(&uid_str).join(&args[3]);
-        let upper = delta_dir.join("upper");
-        let work = delta_dir.join("work");
-        let merged = delta_dir.join("merged");
-
-        // we only attempt the fuse dance if we aren't on android OR if /dev/fuse is miraculously open
-        let use_overlay = !is_android || has_fuse;
-
-        if use_overlay {
-            infoln("box", "launching namespaced session with proot...");
-
-            for dir in [&upper, &work, &merged] {
-                fs::create_dir_all(dir).unwrap();
-            }
-
-            let fuse_bin = ONYX_DIR.join("bin/fuse-overlayfs");
-            let proot_bin = ONYX_DIR.join("bin/proot");
-            let shell = "/bin/bash"; // or use your find_shell logic
-
-            // we build one giant command string that unshare executes
-            // 1. mount the fuse layer
-            // 2. run proot pointing to the newly merged layer
-            let chain_cmd = format!(
-                "{} -f -o lowerdir={},upperdir={},workdir={},squash_to_root {} & sleep 1 && {} -r {} -0 -b /dev -b /proc -b /sys --link2symlink -w / {}",
-                fuse_bin.display(),
-                sys_path.display(),
-                upper.display(),
-                work.display(),
-                merged.display(),
-                proot_bin.display(),
-                merged.display(),
-                shell
-            );
-
-            let status = Command::new("unshare")
-                .env_clear() // kill everything termux gave us
-                .env("HOME", "/root")
-                .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-                .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-                .env("PROOT_TMP_DIR", ONYX_DIR.join("tmp"))
-                .args(&["-U", "-r", "-m", "bash", "-c", &chain_cmd])
-                .status()
-                .expect("failed to execute namespaced chain");
-
-            if !status.success() {
-                errln("box", "session failed, falling back...");
-                run_standalone_proot(&sys_path);
-            }
-        } else {
-            infoln("box", "android detected: using standalone mode (no delta)");
-            run_standalone_proot(&sys_path);
-        }
-        return;
+    if command.is_empty() {
+        errln("box", "no command provided to exec");
+        return EXIT_ONYX;
     }
 
-    infoln("box", "running as root user with chroot");
+    // --shell keeps the old behaviour: the words are joined and run by the box's shell
+    let strcommand = command.join(" ");
+    let target = if flags.iter().any(|a| a == "--shell") {
+        Target::Script(strcommand.clone())
+    } else {
+        Target::Argv(command.to_vec())
+    };
 
-    // try to unshare mount namespace first
-    match try_unshare_mount_ns() {
-        Ok(_) => infoln("box", "entered new mount namespace (isolation enabled)"),
+    let (session, _guards) = match prepare_session(&args[3], flags, target) {
+        Ok(s) => s,
         Err(e) => {
-            // fallback: make mounts private on the host (best-effort)
-            errln("box", &format!("couldn't create mount namespace: {}. falling back to private mounts.", e));
-            if let Err(e2) = run("mount", &["--make-rprivate", "/"]) {
-                errln("box", &format!("failed to make / rprivate: {}", e2));
-                errln("box", "refusing to proceed without isolation");
-                return;
-            }
+            errln("box", &e);
+            return EXIT_ONYX;
         }
+    };
+
+    infoln("box", &format!("executing box command: {}", strcommand));
+    crate::backend::launch(&session, backend_flag(flags))
+}
+
+fn open(args: Vec<String>) {
+    if args.len() < 4 {
+        errln("box", "no system provided to open");
+        return;
     }
 
-    // RAII mount guard
-    let guard = match MountGuard::new(&sys_path, Some(&geteuid().to_string()), &args[3]) {
-        Ok(m) => m,
+    let flags = &args[4..];
+    let (session, _guards) = match prepare_session(&args[3], flags, Target::Login) {
+        Ok(s) => s,
         Err(e) => {
             errln("box", &e);
             return;
         }
     };
 
-    let shell = find_shell(guard.root());
+    infoln("box", &format!("entering box with {}", session.opts.shell));
 
-    infoln("box", &format!("entering box with {}", shell));
-
-    #[cfg(target_os = "android")]
-    match Command::new("chroot")
-        .env_clear() // kill everything termux gave us
-        .env("HOME", "/root")
-        .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-        .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-        .env_remove("LD_PRELOAD")
-        .arg(guard.root())
-        .arg(shell)
-        .status()
-    {
-        Ok(_) => {} // shell finished, ignore exit code
-        Err(e) => errln("box", &format!("chroot failed: {}", e)),
-    }
-
-    #[cfg(not(target_os = "android"))]
-    match Command::new("chroot")
-        .env_clear() // kill everything termux gave us
-        .env("HOME", "/root")
-        .env("TERM", std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string()))
-        .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
-        .env_remove("LD_PRELOAD")
-        .arg(guard.root())
-        .arg(shell)
-        .status()
-    {
-        Ok(_) => {} // shell finished, ignore exit code
-        Err(e) => errln("box", &format!("chroot failed: {}", e)),
-    }
-
-    // when function exits, MountGuard is dropped and unmounts occur inside
+    // the shell's exit status is the user's business, not ours
+    let _ = crate::backend::launch(&session, backend_flag(flags));
     infoln("box", "exited box");
-    infoln("box", "unmounting...");
 }
 
 /// creates a new box by either copying or moving a rootfs
-fn create_box(name: &str, source_path: &Path, move_mode: bool) -> std::io::Result<()> {
+fn create_box(name: &str, source_path: &Path, move_mode: bool, method: crate::copy::Method) -> std::io::Result<()> {
+    if !valid_box_name(name) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid box name '{}'", name)));
+    }
     let onyx_dir = std::env::var("ONYX_DIR").unwrap_or_else(|_| "/home/onyx".to_string());
     let target_dir = PathBuf::from(onyx_dir).join("sys").join(name);
 
@@ -789,15 +1050,249 @@ fn create_box(name: &str, source_path: &Path, move_mode: bool) -> std::io::Resul
         // brute move: fast, but only works on same mount point
         fs::rename(source_path, &target_dir)?;
     } else {
-        // brute copy: slow, works everywhere
-        // note: in real life, use a crate like `fs_extra` for recursive copy
-        copy_recursive(source_path, &target_dir)?;
+        // faithful copy: keeps links, devices, ownership and xattrs
+        let stats = match crate::copy::copy_tree(source_path, &target_dir, method, false) {
+            Ok(stats) => stats,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&target_dir);
+                return Err(e);
+            }
+        };
+        if stats.fallbacks > 0 {
+            infoln("box", &format!("{} file(s) couldn't be shared with the source and were copied in full", stats.fallbacks));
+        }
+        if stats.skipped_devices > 0 {
+            errln("box", &format!("skipped {} device node(s): only root can create them", stats.skipped_devices));
+        }
     }
 
+    let source = fs::canonicalize(source_path).unwrap_or_else(|_| source_path.to_path_buf());
+    let mode = if move_mode { "move" } else { "copy" };
+    write_manifest(name, &target_dir, format!("{}:{}", mode, source.display()))?;
+
     println!("{BLUE}[box]{ESC} box '{}' created successfully at {:?}", name, target_dir);
     Ok(())
 }
 
+/// creates a thin box that stacks its own (empty) layer over `parent`
+fn create_derived_box(name: &str, parent: &str) -> io::Result<()> {
+    if !valid_box_name(name) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid box name '{}'", name)));
+    }
+    let target_dir = ONYX_DIR.join("sys").join(name);
+    let parent_dir = ONYX_DIR.join("sys").join(parent);
+
+    if target_dir.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("box '{}' already exists", name)));
+    }
+    if !parent_dir.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("parent box '{}' does not exist", parent)));
+    }
+
+    fs::create_dir_all(&target_dir)?;
+
+    // the child starts out identical to its parent, so inherit what we know
+    let inherited = crate::manifest::load(parent).unwrap_or_default();
+    let layers = crate::manifest::layers(parent);
+    let manifest = BoxManifest {
+        name: name.to_string(),
+        created: crate::helpers::time_get(),
+        source: format!("derived:{}", parent),
+        arch: inherited.arch.or_else(|| crate::manifest::detect_arch(&parent_dir)),
+        distro: inherited.distro.or_else(|| crate::manifest::detect_distro(&parent_dir)),
+        shell: inherited.shell.or_else(|| Some(find_shell_in(&layers))),
+        profile: inherited.profile,
+        env: inherited.env,
+        pass_env: inherited.pass_env,
+        binds: inherited.binds,
+        // a hostname names one box; the child defaults to its own name
+        hostname: None,
+        parent: Some(parent.to_string()),
+        owner: Some(crate::manifest::detect_owner(&target_dir)),
+    };
+
+    if let Err(e) = crate::manifest::save(&manifest) {
+        let _ = fs::remove_dir_all(&target_dir);
+        return Err(e);
+    }
+
+    println!("{BLUE}[box]{ESC} box '{}' created on top of '{}'", name, parent);
+    Ok(())
+}
+
+/// records a fresh box.toml for a box whose rootfs now sits at `root`
+fn write_manifest(name: &str, root: &Path, source: String) -> io::Result<BoxManifest> {
+    let manifest = BoxManifest {
+        name: name.to_string(),
+        created: crate::helpers::time_get(),
+        source,
+        arch: crate::manifest::detect_arch(root),
+        distro: crate::manifest::detect_distro(root),
+        shell: Some(find_shell(root)),
+        owner: Some(crate::manifest::detect_owner(root)),
+        ..Default::default()
+    };
+    crate::manifest::save(&manifest)?;
+    Ok(manifest)
+}
+
+/// creates a new box by streaming a rootfs tarball into it
+fn import_box(name: &str, archive: &Path) -> std::io::Result<()> {
+    if !valid_box_name(name) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid box name '{}'", name)));
+    }
+
+    let target_dir = ONYX_DIR.join("sys").join(name);
+    if target_dir.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("box '{}' already exists", name)));
+    }
+
+    if !archive.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("archive {} not found", archive.display())));
+    }
+
+    infoln("box", &format!("importing {} into '{}'...", archive.display(), name));
+
+    match crate::tar::extract_archive(archive, &target_dir) {
+        Ok((compression, exported)) => {
+            let source = fs::canonicalize(archive).unwrap_or_else(|_| archive.to_path_buf());
+            let mut manifest = write_manifest(name, &target_dir, format!("import:{}", source.display()))?;
+
+            // boxes exported by onyx carry their settings along
+            if let Some(settings) = exported.and_then(|m| m.settings) {
+                manifest.shell = settings.shell.or(manifest.shell);
+                manifest.profile = settings.profile;
+                manifest.env = settings.env;
+                manifest.pass_env = settings.pass_env;
+                manifest.binds = settings.binds;
+                manifest.hostname = settings.hostname;
+                crate::manifest::save(&manifest)?;
+            }
+
+            infoln("box", &format!("box '{}' imported successfully ({} archive)", name, compression.name()));
+            Ok(())
+        }
+        Err(e) => {
+            // don't leave a half-extracted box lying around
+            let _ = fs::remove_dir_all(&target_dir);
+            Err(e)
+        }
+    }
+}
+
+/// writes a box (optionally with a user's delta flattened on top) to a tarball
+fn export_box(name: &str, out: &Path, delta_user: Option<&str>) -> std::io::Result<()> {
+    let base = ONYX_DIR.join("sys").join(name);
+
+    if !base.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("box '{}' does not exist", name)));
+    }
+
+    if out.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", out.display())));
+    }
+
+    // layers are ordered top to bottom
+    let mut layers = Vec::new();
+    let mut delta_uid = None;
+
+    if let Some(user) = delta_user {
+        let uid = resolve_uid(user)?;
+        let upper = ONYX_DIR.join("delta").join(&uid).join(name).join("upper");
+        if !upper.exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no delta found for user {} at {}", user, upper.display())));
+        }
+        layers.push(upper);
+        delta_uid = Some(uid);
+    }
+    layers.extend(crate::manifest::layers(name));
+
+    let manifest = crate::tar::ExportManifest {
+        format: 1,
+        name: name.to_string(),
+        exported: crate::helpers::time_get(),
+        onyx_version: crate::doctor::VERSION.to_string(),
+        delta_user: delta_user.map(|s| s.to_string()),
+        delta_uid,
+        settings: crate::manifest::load(name),
+    };
+
+    infoln("box", &format!("exporting '{}' to {}...", name, out.display()));
+    let compression = crate::tar::export_layers(&layers, out, &manifest)?;
+    infoln("box", &format!("box '{}' exported successfully ({} archive)", name, compression.name()));
+
+    Ok(())
+}
+
+/// shows or edits a box's manifest settings
+fn config_box(name: &str, flags: &[String]) -> io::Result<()> {
+    let sys_path = ONYX_DIR.join("sys").join(name);
+    if !sys_path.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("box '{}' does not exist", name)));
+    }
+
+    // boxes from before box.toml get one on first config
+    let mut manifest = match crate::manifest::load(name) {
+        Some(m) => m,
+        None => write_manifest(name, &sys_path, "unknown".to_string())?,
+    };
+
+    for arg in flags {
+        if let Some(val) = arg.strip_prefix("--shell=") {
+            manifest.shell = if val.is_empty() { None } else { Some(val.to_string()) };
+        } else if let Some(val) = arg.strip_prefix("--profile=") {
+            manifest.profile = if val.is_empty() { None } else { Some(val.to_string()) };
+        } else if let Some(val) = arg.strip_prefix("--hostname=") {
+            if !val.is_empty() && !valid_hostname(val) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid hostname '{}'", val)));
+            }
+            manifest.hostname = if val.is_empty() { None } else { Some(val.to_string()) };
+        } else if let Some(val) = arg.strip_prefix("--env=") {
+            let Some((k, v)) = val.split_once('=') else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid env '{}', expected K=V", val)));
+            };
+            manifest.env.insert(k.to_string(), v.to_string());
+        } else if let Some(val) = arg.strip_prefix("--unset-env=") {
+            manifest.env.remove(val);
+        } else if let Some(val) = arg.strip_prefix("--env-file=") {
+            manifest.env.extend(crate::manifest::parse_env_file(Path::new(val))?);
+        } else if let Some(val) = arg.strip_prefix("--pass-env=") {
+            for name in val.split(',').filter(|n| !n.is_empty()) {
+                if !manifest.pass_env.iter().any(|p| p == name) {
+                    manifest.pass_env.push(name.to_string());
+                }
+            }
+        } else if let Some(val) = arg.strip_prefix("--unpass-env=") {
+            manifest.pass_env.retain(|p| p != val);
+        } else if let Some(val) = arg.strip_prefix("--bind=") {
+            let Some(mut bind) = crate::manifest::parse_bind(val) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid bind '{}', expected <host>:<guest>[:ro]", val)));
+            };
+            // stored binds outlive the cwd they were typed in
+            bind.host = fs::canonicalize(&bind.host)?.to_string_lossy().to_string();
+            manifest.binds.retain(|b| crate::manifest::parse_bind(b).map(|old| old.guest != bind.guest).unwrap_or(true));
+            manifest.binds.push(bind.spec());
+        } else if let Some(val) = arg.strip_prefix("--unbind=") {
+            manifest.binds.retain(|b| {
+                crate::manifest::parse_bind(b).map(|old| old.guest != val).unwrap_or(true)
+            });
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown flag '{}'", arg)));
+        }
+    }
+
+    if !flags.is_empty() {
+        crate::manifest::save(&manifest)?;
+        infoln("box", &format!("updated settings for '{}'", name));
+    }
+
+    let toml_str = toml::to_string_pretty(&manifest)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    println!("{BLUEB}[>== {} ==<]{ESC}", name);
+    print!("{}", toml_str);
+    Ok(())
+}
+
 /// deletes the system entirely
 fn delete_box(name: &str) -> std::io::Result<()> {
     let onyx_dir = std::env::var("ONYX_DIR").unwrap_or_else(|_| "/home/onyx".to_string());
@@ -808,21 +1303,216 @@ fn delete_box(name: &str) -> std::io::Result<()> {
         std::process::exit(1);
     }
 
+    // derived boxes read straight out of their parent's rootfs
+    let children = crate::manifest::children(name);
+    if !children.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("box is the parent of {}; delete those first", children.join(", ")),
+        ));
+    }
+
     fs::remove_dir_all(target_dir)?;
+    crate::manifest::remove(name)?;
     println!("{BLUE}[box]{ESC} box '{}' nuked.", name);
     Ok(())
 }
 
-fn copy_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
-    fs::create_dir_all(dst)?;
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            copy_recursive(&entry.path(), &dst.join(entry.file_name()))?;
-        } else {
-            fs::copy(entry.path(), dst.join(entry.file_name()))?;
+/// letters, digits, '-' and '.', at most 64 bytes (the kernel's limit)
+fn valid_hostname(name: &str) -> bool {
+    name.len() <= 64
+        && !name.starts_with(['-', '.'])
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+}
+
+/// the hostname a box gets when it has none of its own: its name, made valid
+fn box_hostname(box_name: &str) -> String {
+    let name: String = box_name.chars().map(|c| if c == '_' { '-' } else { c }).take(64).collect();
+    let name = name.trim_start_matches(['-', '.']);
+    if name.is_empty() || !valid_hostname(name) {
+        return "onyx".to_string();
+    }
+    name.to_string()
+}
+
+/// box names end up as path components everywhere under ONYX_DIR
+fn valid_box_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// refuse if anyone is inside `name`, or inside a box stacked on top of it
+fn refuse_if_in_use(name: &str) -> io::Result<()> {
+    let mut busy = crate::session::active_any(name);
+    for child in crate::manifest::children(name) {
+        busy.extend(crate::session::active_any(&child));
+    }
+
+    if !busy.is_empty() {
+        let pids: Vec<u32> = busy.iter().map(|(_, pid)| *pid).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("box '{}' is in use (pids {:?}); exit those sessions first", name, pids),
+        ));
+    }
+    Ok(())
+}
+
+/// the uids that have a `<kind>/<uid>/<name>` dir (kind = delta or snapshots)
+fn per_user_dirs(kind: &str, name: &str) -> Vec<PathBuf> {
+    let Ok(uids) = fs::read_dir(ONYX_DIR.join(kind)) else {
+        return Vec::new();
+    };
+    uids.filter_map(Result::ok)
+        .map(|e| e.path().join(name))
+        .filter(|p| p.exists())
+        .collect()
+}
+
+/// move a box with its manifest, every user's delta and snapshots to a new name
+fn rename_box(old: &str, new: &str) -> io::Result<()> {
+    if !valid_box_name(new) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid box name '{}'", new)));
+    }
+    if !ONYX_DIR.join("sys").join(old).exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("box '{}' does not exist", old)));
+    }
+    refuse_if_in_use(old)?;
+
+    // every place the old name shows up, paired with where it goes
+    let mut moves: Vec<(PathBuf, PathBuf)> = vec![(ONYX_DIR.join("sys").join(old), ONYX_DIR.join("sys").join(new))];
+    let meta = crate::manifest::meta_dir(old);
+    if meta.exists() {
+        moves.push((meta, crate::manifest::meta_dir(new)));
+    }
+    for kind in ["delta", "snapshots"] {
+        for dir in per_user_dirs(kind, old) {
+            let to = dir.with_file_name(new);
+            moves.push((dir, to));
+        }
+    }
+
+    if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+
+    let children = crate::manifest::children(old);
+
+    let mut done: Vec<&(PathBuf, PathBuf)> = Vec::new();
+    let result = (|| -> io::Result<()> {
+        for m in &moves {
+            fs::rename(&m.0, &m.1)?;
+            done.push(m);
+        }
+
+        if let Some(mut manifest) = crate::manifest::load(new) {
+            manifest.name = new.to_string();
+            crate::manifest::save(&manifest)?;
+        }
+        for child in &children {
+            if let Some(mut manifest) = crate::manifest::load(child) {
+                manifest.parent = Some(new.to_string());
+                crate::manifest::save(&manifest)?;
+            }
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        // put everything back where it was
+        for (from, to) in done.iter().rev() {
+            if let Err(e) = fs::rename(to, from) {
+                errln("box", &format!("failed to move {} back: {}", to.display(), e));
+            }
+        }
+        if let Some(mut manifest) = crate::manifest::load(old) {
+            manifest.name = old.to_string();
+            let _ = crate::manifest::save(&manifest);
         }
+        for child in &children {
+            if let Some(mut manifest) = crate::manifest::load(child) {
+                manifest.parent = Some(old.to_string());
+                let _ = crate::manifest::save(&manifest);
+            }
+        }
+        return Err(e);
     }
+
+    println!("{BLUE}[box]{ESC} box '{}' renamed to '{}'", old, new);
     Ok(())
-}
\ No newline at end of file
+}
+
+/// copy a box (and optionally one user's delta) into an independent new box
+fn duplicate_box(src: &str, dst: &str, delta_user: Option<&str>) -> io::Result<()> {
+    if !valid_box_name(dst) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid box name '{}'", dst)));
+    }
+
+    let src_dir = ONYX_DIR.join("sys").join(src);
+    let dst_dir = ONYX_DIR.join("sys").join(dst);
+    if !src_dir.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("box '{}' does not exist", src)));
+    }
+    if dst_dir.exists() || crate::manifest::meta_dir(dst).exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("box '{}' already exists", dst)));
+    }
+
+    let delta = match delta_user {
+        Some(user) => {
+            let uid = resolve_uid(user)?;
+            let upper = ONYX_DIR.join("delta").join(&uid).join(src).join("upper");
+            if !upper.exists() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("no delta found for user {} at {}", user, upper.display())));
+            }
+            if !crate::session::active(&uid, src).is_empty() {
+                errln("box", "box is running; the copied delta may catch files mid-write");
+            }
+            Some((upper, ONYX_DIR.join("delta").join(&uid).join(dst).join("upper")))
+        }
+        None => None,
+    };
+    if let Some((_, to)) = &delta
+        && to.exists()
+    {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+
+    let result = (|| -> io::Result<()> {
+        infoln("box", &format!("copying '{}'...", src));
+        fs::create_dir_all(&dst_dir)?;
+        // a derived box's own layer is an overlay layer over its parents
+        let derived = crate::manifest::layers(src).len() > 1;
+        crate::copy::copy_tree(&src_dir, &dst_dir, crate::copy::Method::Full, derived)?;
+
+        if let Some((from, to)) = &delta {
+            infoln("box", "copying delta...");
+            fs::create_dir_all(to)?;
+            crate::copy::copy_tree(from, to, crate::copy::Method::Full, true)?;
+        }
+
+        let mut manifest = match crate::manifest::load(src) {
+            Some(m) => m,
+            None => write_manifest(dst, &dst_dir, String::new())?,
+        };
+        manifest.name = dst.to_string();
+        manifest.created = crate::helpers::time_get();
+        manifest.source = format!("duplicate:{}", src);
+        manifest.owner = Some(crate::manifest::detect_owner(&dst_dir));
+        crate::manifest::save(&manifest)
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&dst_dir);
+        let _ = crate::manifest::remove(dst);
+        if let Some((_, to)) = &delta
+            && let Some(parent) = to.parent()
+        {
+            let _ = fs::remove_dir_all(parent);
+        }
+        return Err(e);
+    }
+
+    println!("{BLUE}[box]{ESC} box '{}' duplicated as '{}'", src, dst);
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::helpers::{errln, ONYX_DIR};
+
+//=== cgroup v2 limits ===//
+// profiles are enforced through a cgroup per session when the host lets us
+// have one: the topmost cgroup above our own that we can write to (the
+// root cgroup for root, user@<uid>.service under systemd) gets an `onyx`
+// child, and every session runs in onyx/<box>-<pid> below it. without a
+// usable cgroup v2, `limit_box` falls back to rlimits.
+
+/// controllers sessions use, when the host has them
+const CONTROLLERS: [&str; 4] = ["memory", "cpu", "pids", "io"];
+
+/// what a session's group enforces; None leaves that one at "max"
+#[derive(Debug, Default)]
+pub struct Limits {
+    /// bytes of RAM; swap gets the same again
+    pub memory: Option<u64>,
+    /// cpu bandwidth in percent of one core (200 = two cores' worth)
+    pub cpu: Option<u32>,
+    pub pids: Option<u64>,
+    /// io.weight, 1 to 10000
+    pub io_weight: Option<u16>,
+    /// io.max caps in bytes/s on the disk holding ONYX_DIR
+    pub io_read: Option<u64>,
+    pub io_write: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none()
+            && self.cpu.is_none()
+            && self.pids.is_none()
+            && self.io_weight.is_none()
+            && self.io_read.is_none()
+            && self.io_write.is_none()
+    }
+}
+
+/// "major:minor" of the whole disk ONYX_DIR lives on; io.max only takes disks
+fn onyx_disk() -> Option<String> {
+    let dev = fs::metadata(&*ONYX_DIR).ok()?.dev();
+    let sys = PathBuf::from(format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev)));
+    let disk = if sys.join("partition").exists() { sys.canonicalize().ok()?.parent()?.to_path_buf() } else { sys };
+    fs::read_to_string(disk.join("dev")).ok().map(|s| s.trim().to_string())
+}
+
+/// where the cgroup2 hierarchy is mounted, from /proc/self/mountinfo
+fn mount_point() -> Option<PathBuf> {
+    let info = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    info.lines().find_map(|line| {
+        let (mount, fs) = line.split_once(" - ")?;
+        if fs.split_whitespace().next()? != "cgroup2" {
+            return None;
+        }
+        mount.split_whitespace().nth(4).map(PathBuf::from)
+    })
+}
+
+/// our own cgroup, relative to the mount point
+fn own_cgroup() -> Option<String> {
+    let data = fs::read_to_string("/proc/self/cgroup").ok()?;
+    data.lines().find_map(|l| l.strip_prefix("0::")).map(|p| p.trim_start_matches('/').to_string())
+}
+
+fn writable(path: &Path) -> bool {
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// the cgroup onyx may create session groups under, or why there isn't one.
+/// doctor uses this to report the limit mode.
+pub fn delegated() -> Result<PathBuf, String> {
+    let mount = mount_point().ok_or("cgroup v2 is not mounted")?;
+    let own = own_cgroup().ok_or("this process isn't in a cgroup v2 hierarchy")?;
+
+    // walk down from the root; the first group we may manage is ours to use
+    let mut dir = mount.clone();
+    let mut parts = own.split('/').filter(|p| !p.is_empty());
+    let base = loop {
+        if writable(&dir) && writable(&dir.join("cgroup.subtree_control")) {
+            break dir;
+        }
+        match parts.next() {
+            Some(part) => dir.push(part),
+            None => return Err("no cgroup has been delegated to this user".to_string()),
+        }
+    };
+
+    let available = fs::read_to_string(base.join("cgroup.controllers")).unwrap_or_default();
+    if !available.split_whitespace().any(|c| c == "memory") {
+        return Err(format!("the memory controller isn't available in {}", base.display()));
+    }
+    Ok(base)
+}
+
+/// turn on our controllers for the children of `dir`
+fn enable_controllers(dir: &Path, controllers: &[&str]) -> Result<(), String> {
+    let line = controllers.iter().map(|c| format!("+{}", c)).collect::<Vec<_>>().join(" ");
+    fs::write(dir.join("cgroup.subtree_control"), line)
+        .map_err(|e| format!("failed to enable {} in {}: {}", controllers.join(", "), dir.display(), e))
+}
+
+fn write(group: &Path, file: &str, value: String) -> Result<(), String> {
+    fs::write(group.join(file), &value).map_err(|e| format!("failed to set {} to {}: {}", file, value, e))
+}
+
+/// RAII cgroup for a session. onyx moves itself in, so everything it starts
+/// inherits the limits; dropping it moves onyx back and removes the group.
+pub struct SessionGroup {
+    path: PathBuf,
+    home: PathBuf,
+}
+
+impl SessionGroup {
+    pub fn enter(box_name: &str, limits: &Limits) -> Result<Self, String> {
+        let base = delegated()?;
+        let mount = mount_point().ok_or("cgroup v2 is not mounted")?;
+        let home = mount.join(own_cgroup().unwrap_or_default());
+
+        let available = fs::read_to_string(base.join("cgroup.controllers")).unwrap_or_default();
+        let controllers: Vec<&str> =
+            CONTROLLERS.into_iter().filter(|c| available.split_whitespace().any(|a| a == *c)).collect();
+
+        // a group with controllers for its children can't hold processes itself,
+        // hence the extra onyx level between base and the sessions
+        enable_controllers(&base, &controllers)?;
+        let onyx = base.join("onyx");
+        fs::create_dir_all(&onyx).map_err(|e| format!("failed to create {}: {}", onyx.display(), e))?;
+        enable_controllers(&onyx, &controllers)?;
+
+        let path = onyx.join(format!("{}-{}", box_name, std::process::id()));
+        fs::create_dir_all(&path).map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+        let group = Self { path, home };
+
+        if let Some(bytes) = limits.memory {
+            write(&group.path, "memory.max", bytes.to_string())?;
+            // reclaim starts a bit early, so the box slows down before it gets killed
+            write(&group.path, "memory.high", (bytes / 10 * 9).to_string())?;
+            // no swap accounting on some kernels; the hard limit still holds
+            if group.path.join("memory.swap.max").exists() {
+                write(&group.path, "memory.swap.max", bytes.to_string())?;
+            }
+        }
+        if let Some(percent) = limits.cpu {
+            write(&group.path, "cpu.max", format!("{} 100000", percent as u64 * 1000))?;
+        }
+        if let Some(pids) = limits.pids {
+            write(&group.path, "pids.max", pids.to_string())?;
+        }
+        if limits.io_weight.is_some() || limits.io_read.is_some() || limits.io_write.is_some() {
+            if controllers.contains(&"io") {
+                group.limit_io(limits)?;
+            } else {
+                errln("box", "the io controller isn't available; skipping io.weight and io.max");
+            }
+        }
+
+        write(&group.path, "cgroup.procs", std::process::id().to_string())?;
+        Ok(group)
+    }
+
+    fn limit_io(&self, limits: &Limits) -> Result<(), String> {
+        if let Some(weight) = limits.io_weight {
+            write(&self.path, "io.weight", format!("default {}", weight))?;
+        }
+        if limits.io_read.is_none() && limits.io_write.is_none() {
+            return Ok(());
+        }
+
+        // overlay, tmpfs and friends have no block device to cap
+        let Some(disk) = onyx_disk() else {
+            errln("box", &format!("{} isn't on a block device; skipping io.max", ONYX_DIR.display()));
+            return Ok(());
+        };
+        let mut line = disk;
+        if let Some(rbps) = limits.io_read {
+            line.push_str(&format!(" rbps={}", rbps));
+        }
+        if let Some(wbps) = limits.io_write {
+            line.push_str(&format!(" wbps={}", wbps));
+        }
+        write(&self.path, "io.max", line)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SessionGroup {
+    fn drop(&mut self) {
+        let _ = fs::write(self.home.join("cgroup.procs"), std::process::id().to_string());
+        // fails, leaving the group behind, if something from the box is still running
+        let _ = fs::remove_dir(&self.path);
+    }
+}
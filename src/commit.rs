@@ -0,0 +1,665 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::copy;
+use crate::helpers::{errln, infoln, rooted, BLUE, BLUEB, DIM, ESC, GREEN, ONYX_DIR, RED, YELLOW};
+use crate::layer;
+
+//=== delta commit ===//
+// merges ONYX_DIR/delta/<uid>/<box>/upper into the box's own layer in
+// ONYX_DIR/sys/<box>. the whole plan is written to a journal in
+// ONYX_DIR/delta/<uid>/<box>/commit before the box is touched, and every
+// entry the merge replaces or removes is moved into commit/backup first,
+// so an interrupted merge can be resumed or rolled back.
+//
+// derived boxes only own their top layer: deleting something that comes
+// from a parent leaves a whiteout behind instead of just removing it.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    /// move the entry out of the box
+    Remove,
+    /// hide the entry in the parent layers (derived boxes)
+    Whiteout,
+    /// (re)create a directory
+    Dir,
+    /// mark a directory as hiding the parent layers (derived boxes)
+    Opaque,
+    /// copy a non-directory entry from the upper layer
+    File,
+    /// hardlink to an entry copied earlier in the plan
+    Link,
+    /// apply the upper directory's mode, owner, xattrs and times
+    Meta,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Step {
+    op: Op,
+    path: PathBuf,
+    /// hardlink target, for Op::Link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<PathBuf>,
+    /// entries in the box this step moves into the backup before it runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    displace: Vec<PathBuf>,
+    /// mode, uid and gid of a directory before Op::Meta touched it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    old: Option<(u32, u32, u32)>,
+}
+
+/// what to do about a merge left behind by an earlier run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Merge,
+    DryRun,
+    Resume,
+    Rollback,
+}
+
+struct Paths {
+    brick: PathBuf,
+    upper: PathBuf,
+    journal: PathBuf,
+    backup: PathBuf,
+}
+
+impl Paths {
+    fn new(uid: &str, box_name: &str) -> Self {
+        let delta = ONYX_DIR.join("delta").join(uid).join(box_name);
+        let journal = delta.join("commit");
+        Self {
+            brick: ONYX_DIR.join("sys").join(box_name),
+            upper: delta.join("upper"),
+            backup: journal.join("backup"),
+            journal,
+        }
+    }
+
+    fn plan_file(&self) -> PathBuf {
+        self.journal.join("plan.json")
+    }
+
+    fn done_file(&self) -> PathBuf {
+        self.journal.join("done")
+    }
+}
+
+/// is there an unfinished merge for this delta?
+pub fn pending(uid: &str, box_name: &str) -> bool {
+    Paths::new(uid, box_name).plan_file().exists()
+}
+
+//=== planning ===//
+fn wh_sibling(rel: &Path) -> Option<PathBuf> {
+    let name = rel.file_name()?.to_str()?;
+    Some(rel.with_file_name(format!("{}{}", layer::WHITEOUT_PREFIX, name)))
+}
+
+fn build_plan(p: &Paths, parents: &[PathBuf]) -> io::Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    let mut metas = Vec::new();
+    // dirs recreated from scratch; nothing under them is in the box anymore
+    let mut fresh: Vec<PathBuf> = Vec::new();
+    let mut links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+
+    let in_box = |rel: &Path, fresh: &[PathBuf]| -> bool {
+        !fresh.iter().any(|f| rel.starts_with(f)) && p.brick.join(rel).symlink_metadata().is_ok()
+    };
+    let displace_for = |rel: &Path, fresh: &[PathBuf]| -> Vec<PathBuf> {
+        let mut out = Vec::new();
+        if in_box(rel, fresh) {
+            out.push(rel.to_path_buf());
+        }
+        if let Some(wh) = wh_sibling(rel)
+            && in_box(&wh, fresh)
+        {
+            out.push(wh);
+        }
+        out
+    };
+    let step = |op: Op, path: &Path, displace: Vec<PathBuf>| Step {
+        op,
+        path: path.to_path_buf(),
+        target: None,
+        displace,
+        old: None,
+    };
+
+    for entry in WalkDir::new(&p.upper).follow_links(false).sort_by_file_name().min_depth(1) {
+        let entry = entry.map_err(io::Error::other)?;
+        let path = entry.path();
+        let rel = path.strip_prefix(&p.upper).map_err(io::Error::other)?;
+        let meta = entry.metadata().map_err(io::Error::other)?;
+
+        if path.file_name().is_some_and(|n| n == layer::OPAQUE_MARKER) {
+            continue;
+        }
+
+        if let Some(target) = layer::whiteout_target(path, &meta) {
+            let target = target.strip_prefix(&p.upper).map_err(io::Error::other)?;
+            let displace = displace_for(target, &fresh);
+            let shadowed = layer::find(parents, target).is_some();
+            if !displace.is_empty() {
+                steps.push(step(Op::Remove, target, displace));
+            }
+            if shadowed {
+                steps.push(step(Op::Whiteout, target, Vec::new()));
+            }
+            continue;
+        }
+
+        if meta.is_dir() {
+            let opaque = layer::is_opaque(path);
+            let existing = if fresh.iter().any(|f| rel.starts_with(f)) {
+                None
+            } else {
+                fs::symlink_metadata(p.brick.join(rel)).ok()
+            };
+            let is_real_dir = existing.as_ref().is_some_and(|m| m.is_dir());
+            // a plain dir over our own whiteout would let the parents' contents back in
+            let over_whiteout = existing.as_ref().is_some_and(|m| layer::is_whiteout(&p.brick.join(rel), m))
+                || wh_sibling(rel).is_some_and(|wh| in_box(&wh, &fresh));
+
+            let mut old = None;
+            if opaque || !is_real_dir {
+                steps.push(step(Op::Dir, rel, displace_for(rel, &fresh)));
+                fresh.push(rel.to_path_buf());
+            } else if let Some(m) = &existing {
+                old = Some((m.mode(), m.uid(), m.gid()));
+            }
+
+            if over_whiteout || (opaque && layer::find(parents, rel).is_some()) {
+                steps.push(step(Op::Opaque, rel, Vec::new()));
+            }
+
+            metas.push(Step { old, ..step(Op::Meta, rel, Vec::new()) });
+            continue;
+        }
+
+        let displace = displace_for(rel, &fresh);
+        if meta.nlink() > 1 && !meta.is_dir() {
+            let key = (meta.dev(), meta.ino());
+            if let Some(first) = links.get(&key) {
+                steps.push(Step { target: Some(first.clone()), ..step(Op::Link, rel, displace) });
+                continue;
+            }
+            links.insert(key, rel.to_path_buf());
+        }
+        steps.push(step(Op::File, rel, displace));
+    }
+
+    // deepest dirs first, once everything inside them is in place
+    metas.reverse();
+    steps.extend(metas);
+    Ok(steps)
+}
+
+//=== journal ===//
+fn write_plan(p: &Paths, steps: &[Step]) -> io::Result<()> {
+    fs::create_dir_all(&p.journal)?;
+    let tmp = p.journal.join("plan.json.tmp");
+    let data = serde_json::to_vec(steps).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(&data)?;
+    f.sync_all()?;
+    fs::File::create(p.done_file())?.sync_all()?;
+    fs::rename(tmp, p.plan_file())
+}
+
+fn read_plan(p: &Paths) -> io::Result<Vec<Step>> {
+    let data = fs::read(p.plan_file())?;
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("corrupt commit journal: {}", e)))
+}
+
+/// number of steps known to be finished
+fn read_done(p: &Paths) -> usize {
+    fs::File::open(p.done_file())
+        .map(|f| BufReader::new(f).lines().map_while(Result::ok).filter(|l| !l.is_empty()).count())
+        .unwrap_or(0)
+}
+
+fn mark_done(done: &mut fs::File, idx: usize) -> io::Result<()> {
+    writeln!(done, "{}", idx)?;
+    done.sync_data()
+}
+
+//=== steps ===//
+fn remove_any(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// rename, falling back to copy + delete across filesystems
+fn move_path(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            for entry in WalkDir::new(src).follow_links(false) {
+                let entry = entry.map_err(io::Error::other)?;
+                let rel = entry.path().strip_prefix(src).map_err(io::Error::other)?;
+                let meta = entry.metadata().map_err(io::Error::other)?;
+                let to = if rel.as_os_str().is_empty() { dst.to_path_buf() } else { dst.join(rel) };
+                copy::copy_entry(entry.path(), &to, &meta)?;
+            }
+            remove_any(src)
+        }
+        other => other,
+    }
+}
+
+/// move what the plan said was in the box out of the way. anything else at
+/// those paths was put there by an earlier, interrupted run of this merge.
+fn displace(p: &Paths, rels: &[PathBuf]) -> io::Result<()> {
+    for rel in rels {
+        let (live, saved) = (p.brick.join(rel), p.backup.join(rel));
+        if saved.symlink_metadata().is_ok() {
+            remove_any(&live)?;
+        } else if live.symlink_metadata().is_ok() {
+            if let Some(parent) = saved.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            move_path(&live, &saved)?;
+        }
+    }
+    Ok(())
+}
+
+fn restore(p: &Paths, rels: &[PathBuf]) -> io::Result<()> {
+    for rel in rels {
+        let (live, saved) = (p.brick.join(rel), p.backup.join(rel));
+        if saved.symlink_metadata().is_ok() {
+            remove_any(&live)?;
+            move_path(&saved, &live)?;
+        }
+    }
+    Ok(())
+}
+
+/// where a file is assembled before it's renamed over its final path
+fn staging_path(live: &Path) -> PathBuf {
+    let name = live.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    live.with_file_name(format!(".onyx-commit.{}", name))
+}
+
+fn make_whiteout(live: &Path) -> io::Result<()> {
+    if rooted() {
+        if live.symlink_metadata().is_err() {
+            copy::mknod(live, libc::S_IFCHR, 0)?;
+        }
+        return Ok(());
+    }
+
+    // unprivileged: the aufs style, which fuse-overlayfs understands too
+    let Some(wh) = live.file_name().and_then(|n| n.to_str()).map(|n| live.with_file_name(format!("{}{}", layer::WHITEOUT_PREFIX, n))) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad whiteout path"));
+    };
+    fs::write(wh, "")
+}
+
+fn apply_step(p: &Paths, s: &Step) -> io::Result<()> {
+    let live = p.brick.join(&s.path);
+    let src = p.upper.join(&s.path);
+
+    match s.op {
+        Op::Remove => displace(p, &s.displace),
+        Op::Whiteout => make_whiteout(&live),
+        Op::Dir => {
+            displace(p, &s.displace)?;
+            if !live.is_dir() {
+                fs::create_dir(&live)?;
+            }
+            Ok(())
+        }
+        Op::Opaque => {
+            if rooted() {
+                xattr::set(&live, "trusted.overlay.opaque", b"y")
+            } else {
+                fs::write(live.join(layer::OPAQUE_MARKER), "")
+            }
+        }
+        Op::File | Op::Link => {
+            let staged = staging_path(&live);
+            remove_any(&staged)?;
+
+            if s.op == Op::Link {
+                let first = s.target.as_ref().ok_or_else(|| io::Error::other("link step without a target"))?;
+                fs::hard_link(p.brick.join(first), &staged)?;
+            } else {
+                let meta = fs::symlink_metadata(&src)?;
+                copy::copy_entry(&src, &staged, &meta)?;
+            }
+
+            displace(p, &s.displace)?;
+            fs::rename(&staged, &live)
+        }
+        Op::Meta => {
+            let meta = fs::symlink_metadata(&src)?;
+            copy::copy_metadata(&src, &live, &meta)
+        }
+    }
+}
+
+fn undo_step(p: &Paths, s: &Step) -> io::Result<()> {
+    let live = p.brick.join(&s.path);
+
+    match s.op {
+        Op::Remove => restore(p, &s.displace),
+        Op::Whiteout => {
+            if let Ok(m) = live.symlink_metadata()
+                && layer::is_whiteout(&live, &m)
+            {
+                fs::remove_file(&live)?;
+            }
+            if let Some(wh) = wh_sibling(&s.path) {
+                let wh = p.brick.join(wh);
+                if wh.symlink_metadata().is_ok() {
+                    fs::remove_file(wh)?;
+                }
+            }
+            Ok(())
+        }
+        Op::Opaque => {
+            let _ = xattr::remove(&live, "trusted.overlay.opaque");
+            let _ = fs::remove_file(live.join(layer::OPAQUE_MARKER));
+            Ok(())
+        }
+        Op::Dir | Op::File | Op::Link => {
+            remove_any(&staging_path(&live))?;
+            // until everything it replaces is safe in the backup, `live` is still
+            // the box's own entry: the step was cut off before it got that far
+            if s.displace.iter().all(|rel| p.backup.join(rel).symlink_metadata().is_ok()) {
+                remove_any(&live)?;
+            }
+            restore(p, &s.displace)
+        }
+        Op::Meta => {
+            if let Some((mode, uid, gid)) = s.old
+                && live.exists()
+            {
+                if rooted() {
+                    copy::chown_nofollow(&live, uid as u64, gid as u64)?;
+                }
+                fs::set_permissions(&live, fs::Permissions::from_mode(mode & 0o7777))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+//=== entry points ===//
+fn describe(s: &Step) -> (&'static str, &'static str) {
+    let replaces = !s.displace.is_empty();
+    match s.op {
+        Op::Remove => ("remove", RED),
+        Op::Whiteout => ("whiteout", RED),
+        Op::Dir if replaces => ("replace dir", YELLOW),
+        Op::Dir => ("mkdir", GREEN),
+        Op::Opaque => ("opaque", YELLOW),
+        Op::File | Op::Link if replaces => ("replace", YELLOW),
+        Op::File => ("add", GREEN),
+        Op::Link => ("link", GREEN),
+        Op::Meta => ("meta", DIM),
+    }
+}
+
+fn print_plan(steps: &[Step], box_name: &str) {
+    println!("{BLUEB}[>== commit plan: {} ==<]{ESC}", box_name);
+
+    let mut shown = 0;
+    for s in steps.iter().filter(|s| s.op != Op::Meta) {
+        let (what, color) = describe(s);
+        match &s.target {
+            Some(t) => println!("    {color}{:<11}{ESC} /{} {DIM}-> /{}{ESC}", what, s.path.display(), t.display()),
+            None => println!("    {color}{:<11}{ESC} /{}", what, s.path.display()),
+        }
+        shown += 1;
+    }
+
+    if shown == 0 {
+        println!("    {DIM}nothing to merge{ESC}");
+    }
+    println!("{BLUE}[summary]{ESC} {} change(s), {} backed up", shown, steps.iter().map(|s| s.displace.len()).sum::<usize>());
+}
+
+fn progress(len: usize) -> ProgressBar {
+    let pb = ProgressBar::new(len as u64);
+    pb.set_style(
+        ProgressStyle::with_template("{spinner:.cyan.bold} [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
+            .unwrap()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏✔")
+            .progress_chars("## "),
+    );
+    pb
+}
+
+fn run_steps(p: &Paths, steps: &[Step], from: usize) -> io::Result<()> {
+    let mut done = OpenOptions::new().append(true).create(true).open(p.done_file())?;
+    let pb = progress(steps.len());
+    pb.set_position(from as u64);
+
+    for (idx, s) in steps.iter().enumerate().skip(from) {
+        if let Err(e) = apply_step(p, s) {
+            pb.abandon();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("step {} ({:?} /{}) failed: {}", idx, s.op, s.path.display(), e),
+            ));
+        }
+        mark_done(&mut done, idx)?;
+        pb.inc(1);
+    }
+
+    pb.finish();
+    Ok(())
+}
+
+/// drop the delta (upper, work, journal) once it's part of the box
+fn finish(p: &Paths) -> io::Result<()> {
+    fs::remove_dir_all(&p.journal)?;
+    if let Some(delta_root) = p.upper.parent()
+        && let Err(e) = fs::remove_dir_all(delta_root)
+    {
+        errln("box", &format!("failed to nuke delta dir: {}", e));
+    }
+    Ok(())
+}
+
+fn rollback(p: &Paths) -> io::Result<()> {
+    let steps = read_plan(p)?;
+    // the step after the last finished one may have been cut off halfway
+    let reached = (read_done(p) + 1).min(steps.len());
+
+    infoln("box", &format!("rolling back {} step(s)...", reached));
+    for s in steps[..reached].iter().rev() {
+        undo_step(p, s).map_err(|e| io::Error::new(e.kind(), format!("undo of /{} failed: {}", s.path.display(), e)))?;
+    }
+
+    fs::remove_dir_all(&p.journal)
+}
+
+/// merge (or preview, resume, roll back) `uid`'s delta into `box_name`
+pub fn commit(uid: &str, box_name: &str, mode: Mode) -> io::Result<()> {
+    let p = Paths::new(uid, box_name);
+    let has_journal = p.plan_file().exists();
+
+    match mode {
+        Mode::Rollback => {
+            if !has_journal {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no interrupted merge to roll back"));
+            }
+            rollback(&p)?;
+            infoln("box", "merge rolled back; the box and the delta are as they were.");
+            return Ok(());
+        }
+        Mode::Resume => {
+            if !has_journal {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no interrupted merge to resume"));
+            }
+            let steps = read_plan(&p)?;
+            let from = read_done(&p);
+            infoln("box", &format!("resuming merge at step {}/{}...", from, steps.len()));
+            run_steps(&p, &steps, from)?;
+            return finish(&p);
+        }
+        Mode::Merge | Mode::DryRun => {}
+    }
+
+    if has_journal {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "an earlier merge was interrupted; use --resume or --rollback first",
+        ));
+    }
+
+    let parents = crate::manifest::layers(box_name).split_off(1);
+    let steps = build_plan(&p, &parents)?;
+
+    if mode == Mode::DryRun {
+        print_plan(&steps, box_name);
+        return Ok(());
+    }
+
+    write_plan(&p, &steps)?;
+    run_steps(&p, &steps, 0)?;
+    finish(&p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// what a tree looks like, as far as a rollback has to put it back
+    fn snapshot(root: &Path) -> BTreeMap<PathBuf, String> {
+        let mut out = BTreeMap::new();
+        for entry in WalkDir::new(root).sort_by_file_name().min_depth(1) {
+            let entry = entry.unwrap();
+            let rel = entry.path().strip_prefix(root).unwrap().to_path_buf();
+            let meta = entry.metadata().unwrap();
+            let desc = if meta.is_dir() {
+                format!("dir {:o} opaque={}", meta.mode() & 0o7777, layer::is_opaque(entry.path()))
+            } else if meta.file_type().is_symlink() {
+                format!("link {}", fs::read_link(entry.path()).unwrap().display())
+            } else {
+                format!("file {}", fs::read_to_string(entry.path()).unwrap_or_default())
+            };
+            out.insert(rel, desc);
+        }
+        out
+    }
+
+    fn write(path: &Path, data: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    /// a derived box's layer, one parent below it and a delta on top
+    fn fixture(tmp: &Path) -> (Paths, Vec<PathBuf>) {
+        let p = Paths {
+            brick: tmp.join("brick"),
+            upper: tmp.join("upper"),
+            journal: tmp.join("commit"),
+            backup: tmp.join("commit/backup"),
+        };
+        let parent = tmp.join("parent");
+
+        write(&parent.join("keep"), "parent keep");
+        write(&parent.join("w/p"), "parent w");
+        write(&parent.join("d/x"), "parent x");
+
+        write(&p.brick.join("a"), "old a");
+        write(&p.brick.join("d/x"), "old x");
+        write(&p.brick.join("keep"), "old keep");
+        write(&p.brick.join(".wh.w"), "");
+        fs::set_permissions(p.brick.join("d"), fs::Permissions::from_mode(0o750)).unwrap();
+
+        write(&p.upper.join("a"), "new a");
+        write(&p.upper.join("n"), "new n");
+        fs::hard_link(p.upper.join("n"), p.upper.join("n2")).unwrap();
+        write(&p.upper.join("d/y"), "new y");
+        write(&p.upper.join(format!("d/{}", layer::OPAQUE_MARKER)), "");
+        write(&p.upper.join("w/z"), "new z");
+        write(&p.upper.join(".wh.keep"), "");
+
+        (p, vec![parent])
+    }
+
+    /// what a run killed partway through step `s` can leave behind
+    fn interrupt(p: &Paths, s: &Step, stage: usize) -> bool {
+        let live = p.brick.join(&s.path);
+        match (s.op, stage) {
+            (_, 0) => {}
+            // the copy died halfway, before anything was displaced
+            (Op::File | Op::Link, 1) => fs::write(staging_path(&live), "partial").unwrap(),
+            (Op::File | Op::Link, 2) => {
+                fs::write(staging_path(&live), "partial").unwrap();
+                displace(p, &s.displace).unwrap();
+            }
+            (Op::Dir | Op::Remove, 1) if !s.displace.is_empty() => displace(p, &s.displace[..1]).unwrap(),
+            (Op::Dir | Op::Remove, 2) if !s.displace.is_empty() => displace(p, &s.displace).unwrap(),
+            _ => return false,
+        }
+        true
+    }
+
+    #[test]
+    fn dir_over_own_whiteout_is_opaque() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (p, parents) = fixture(tmp.path());
+        let steps = build_plan(&p, &parents).unwrap();
+
+        assert!(steps.iter().any(|s| s.op == Op::Opaque && s.path == Path::new("w")));
+
+        write_plan(&p, &steps).unwrap();
+        run_steps(&p, &steps, 0).unwrap();
+        assert_eq!(layer::find(&[p.brick.clone(), parents[0].clone()], Path::new("w/p")), None);
+        assert!(p.brick.join("w/z").exists());
+    }
+
+    #[test]
+    fn rollback_restores_the_box_after_any_interruption() {
+        let steps_len = {
+            let tmp = tempfile::tempdir().unwrap();
+            let (p, parents) = fixture(tmp.path());
+            build_plan(&p, &parents).unwrap().len()
+        };
+
+        for k in 0..=steps_len {
+            for stage in 0..3 {
+                let tmp = tempfile::tempdir().unwrap();
+                let (p, parents) = fixture(tmp.path());
+                let before = snapshot(&p.brick);
+                let steps = build_plan(&p, &parents).unwrap();
+                write_plan(&p, &steps).unwrap();
+
+                let mut done = OpenOptions::new().append(true).open(p.done_file()).unwrap();
+                for (idx, s) in steps[..k].iter().enumerate() {
+                    apply_step(&p, s).unwrap();
+                    mark_done(&mut done, idx).unwrap();
+                }
+                if let Some(s) = steps.get(k)
+                    && !interrupt(&p, s, stage)
+                {
+                    continue;
+                }
+
+                rollback(&p).unwrap();
+                assert_eq!(snapshot(&p.brick), before, "after {} step(s), stage {} of the next", k, stage);
+                assert!(!p.journal.exists());
+            }
+        }
+    }
+}
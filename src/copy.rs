@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use walkdir::WalkDir;
+
+use crate::helpers::rooted;
+use crate::layer;
+
+//=== faithful copies ===//
+// copies a single filesystem entry the way `cp -a` would: type, contents,
+// mode, ownership (when root), xattrs and timestamps. symlinks are never
+// followed. overlay bookkeeping xattrs are left behind, unless a whole
+// layer is copied as a layer.
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))
+}
+
+pub fn chown_nofollow(path: &Path, uid: u64, gid: u64) -> io::Result<()> {
+    let p = c_path(path)?;
+    let res = unsafe { libc::lchown(p.as_ptr(), uid as libc::uid_t, gid as libc::gid_t) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// mknod with `mode` carrying both the file type and permission bits
+pub fn mknod(path: &Path, mode: u32, rdev: u64) -> io::Result<()> {
+    let p = c_path(path)?;
+    let res = unsafe { libc::mknod(p.as_ptr(), mode as libc::mode_t, rdev as libc::dev_t) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// set atime/mtime without following symlinks
+fn set_times(path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    let p = c_path(path)?;
+    let times = [
+        libc::timespec { tv_sec: meta.atime() as libc::time_t, tv_nsec: meta.atime_nsec() as _ },
+        libc::timespec { tv_sec: meta.mtime() as libc::time_t, tv_nsec: meta.mtime_nsec() as _ },
+    ];
+    let res = unsafe { libc::utimensat(libc::AT_FDCWD, p.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// copy xattrs from `src` to `dst`, best effort. returns how many failed.
+pub fn copy_xattrs(src: &Path, dst: &Path) -> usize {
+    copy_xattrs_where(src, dst, |key| !layer::is_overlay_xattr(key))
+}
+
+fn copy_xattrs_where(src: &Path, dst: &Path, keep: impl Fn(&str) -> bool) -> usize {
+    let Ok(names) = xattr::list(src) else {
+        return 0;
+    };
+
+    let mut failed = 0;
+    for name in names {
+        if !keep(&name.to_string_lossy()) {
+            continue;
+        }
+        if let Ok(Some(value)) = xattr::get(src, &name)
+            && xattr::set(dst, &name, &value).is_err()
+        {
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// bring `dst`'s ownership, mode, xattrs and times in line with `src`.
+/// ownership is only touched as root; anyone else gets their own files.
+pub fn copy_metadata(src: &Path, dst: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    if rooted() {
+        chown_nofollow(dst, meta.uid() as u64, meta.gid() as u64)?;
+    }
+
+    // symlink modes can't be changed (and don't mean anything)
+    if !meta.file_type().is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(meta.mode() & 0o7777))?;
+    }
+
+    copy_xattrs(src, dst);
+    set_times(dst, meta)
+}
+
+/// create `dst` as a copy of `src` (described by `meta`). directories are
+/// created empty; their contents are up to the caller.
+pub fn copy_entry(src: &Path, dst: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    let ft = meta.file_type();
+
+    if ft.is_dir() {
+        fs::create_dir(dst)?;
+    } else if ft.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+    } else if ft.is_file() {
+        fs::copy(src, dst)?;
+    } else if ft.is_char_device() || ft.is_block_device() || ft.is_fifo() {
+        mknod(dst, meta.mode(), meta.rdev())?;
+    } else {
+        // sockets belong to whatever process made them; nothing to copy
+        return Ok(());
+    }
+
+    copy_metadata(src, dst, meta)
+}
+
+//=== whole trees ===//
+/// how regular file contents get into the copy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// plain byte copy
+    Full,
+    /// share extents with the source (btrfs, xfs, bcachefs...)
+    Reflink,
+    /// share the inode itself; the box and the source stay tied together
+    Hardlink,
+}
+
+impl Method {
+    pub fn from_name(name: &str) -> Option<Method> {
+        match name {
+            "full" | "copy" => Some(Method::Full),
+            "reflink" => Some(Method::Reflink),
+            "hardlink" => Some(Method::Hardlink),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TreeStats {
+    pub entries: u64,
+    pub bytes: u64,
+    /// files that couldn't use the requested method and were fully copied
+    pub fallbacks: u64,
+    /// device nodes left out because only root can create them
+    pub skipped_devices: u64,
+}
+
+/// clone `src` into a new `dst` with FICLONE
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    let from = fs::File::open(src)?;
+    let to = fs::OpenOptions::new().write(true).create_new(true).open(dst)?;
+
+    let res = unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE as _, from.as_raw_fd()) };
+    if res != 0 {
+        let err = io::Error::last_os_error();
+        drop(to);
+        let _ = fs::remove_file(dst);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// copy the tree under `src` into the existing, empty dir `dst`. with
+/// `raw_layer` the overlay xattrs come along too, so an upper or a derived
+/// box's layer still hides what it hid before.
+pub fn copy_tree(src: &Path, dst: &Path, method: Method, raw_layer: bool) -> io::Result<TreeStats> {
+    let total: u64 = WalkDir::new(src)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum();
+
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::with_template("{spinner:.cyan.bold} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+            .unwrap()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏✔")
+            .progress_chars("## "),
+    );
+
+    let mut stats = TreeStats::default();
+    let mut links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    // dir modes/times go on last, or read-only dirs and new children would fight them
+    let mut dirs: Vec<(PathBuf, PathBuf, fs::Metadata)> = Vec::new();
+
+    let result = (|| -> io::Result<()> {
+        for entry in WalkDir::new(src).follow_links(false).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::other)?;
+            let path = entry.path();
+            let rel = path.strip_prefix(src).map_err(io::Error::other)?;
+            let meta = entry.metadata().map_err(io::Error::other)?;
+            let target = dst.join(rel);
+
+            stats.entries += 1;
+
+            if meta.is_dir() {
+                if !rel.as_os_str().is_empty() {
+                    fs::create_dir(&target)?;
+                }
+                dirs.push((path.to_path_buf(), target, meta));
+                continue;
+            }
+
+            // hardlinks inside the tree stay hardlinks in the copy
+            if meta.nlink() > 1 {
+                let key = (meta.dev(), meta.ino());
+                if let Some(first) = links.get(&key) {
+                    fs::hard_link(first, &target)?;
+                    continue;
+                }
+                links.insert(key, target.clone());
+            }
+
+            let device = meta.file_type().is_char_device() || meta.file_type().is_block_device();
+            if !meta.is_file() || method == Method::Full {
+                match copy_entry(path, &target, &meta) {
+                    // a layer's whiteouts are devices too, and those can't just be dropped
+                    Err(e) if device && !raw_layer && e.raw_os_error() == Some(libc::EPERM) => {
+                        stats.skipped_devices += 1;
+                        continue;
+                    }
+                    other => other?,
+                }
+            } else {
+                let shared = match method {
+                    Method::Hardlink => fs::hard_link(path, &target),
+                    _ => reflink(path, &target),
+                };
+                match shared {
+                    // a hardlink already carries the source's metadata
+                    Ok(_) if method == Method::Hardlink => {}
+                    Ok(_) => copy_metadata(path, &target, &meta)?,
+                    Err(_) => {
+                        stats.fallbacks += 1;
+                        copy_entry(path, &target, &meta)?;
+                    }
+                }
+            }
+
+            if raw_layer && method != Method::Hardlink {
+                copy_xattrs_where(path, &target, layer::is_overlay_xattr);
+            }
+
+            if meta.is_file() {
+                stats.bytes += meta.len();
+                pb.inc(meta.len());
+            }
+        }
+
+        for (from, to, meta) in dirs.iter().rev() {
+            copy_metadata(from, to, meta)?;
+            // opaque dirs are the ones that matter here
+            if raw_layer {
+                copy_xattrs_where(from, to, layer::is_overlay_xattr);
+            }
+        }
+        Ok(())
+    })();
+
+    match result {
+        Ok(_) => pb.finish(),
+        Err(_) => pb.abandon(),
+    }
+    result.map(|_| stats)
+}
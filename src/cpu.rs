@@ -1,7 +1,11 @@
 use std::fs;
 use std::path::Path;
 
+use nix::sched::sched_getaffinity;
+use nix::unistd::Pid;
+
 use crate::helpers::{errln};
+use crate::profile::{CoreSelect, CpuConfig};
 
 #[derive(Debug)]
 struct CpuCore {
@@ -88,6 +92,86 @@ fn compute_onyx_units(cores: &[CpuCore]) -> (f64, f64) {
     (mcu * arch_factor, scu * arch_factor)
 }
 
+//=== core selection ===//
+/// parse a kernel style cpu list, e.g. "0-3,6"
+pub fn parse_core_list(s: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+    for part in s.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let (lo, hi): (usize, usize) = (lo.parse().ok()?, hi.parse().ok()?);
+                if lo > hi {
+                    return None;
+                }
+                cores.extend(lo..=hi);
+            }
+            None => cores.push(part.parse().ok()?),
+        }
+    }
+    Some(cores)
+}
+
+fn online_cores() -> Vec<usize> {
+    fs::read_to_string("/sys/devices/system/cpu/online")
+        .ok()
+        .and_then(|s| parse_core_list(&s))
+        .unwrap_or_else(|| (0..std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)).collect())
+}
+
+/// cores that are online and in the affinity mask onyx inherited
+pub fn usable_cores() -> Vec<usize> {
+    let online = online_cores();
+    match sched_getaffinity(Pid::from_raw(0)) {
+        Ok(mask) => online.into_iter().filter(|&c| mask.is_set(c).unwrap_or(false)).collect(),
+        Err(_) => online,
+    }
+}
+
+fn core_max_khz(core: usize) -> Option<u64> {
+    read_u64(&Path::new("/sys/devices/system/cpu").join(format!("cpu{}", core)).join("cpufreq/scaling_max_freq"))
+}
+
+/// the cores a profile's `[cpu]` section asks for, out of `usable_cores()`:
+/// its `set` if it has one, else `cores` of the kind `select` names
+/// (fastest or slowest first; 0 = all of them)
+pub fn choose_cores(cpu: &CpuConfig) -> Result<Vec<usize>, String> {
+    let usable = usable_cores();
+
+    if let Some(set) = &cpu.set {
+        let chosen: Vec<usize> = set.iter().copied().filter(|c| usable.contains(c)).collect();
+        if chosen.is_empty() {
+            return Err(format!("none of cores {:?} are online and allowed here", set));
+        }
+        if chosen.len() < set.len() {
+            errln("profile", &format!("cores {:?} are offline or not allowed; using {:?}", set, chosen));
+        }
+        return Ok(chosen);
+    }
+
+    let mut candidates: Vec<(usize, u64)> = usable.iter().map(|&c| (c, core_max_khz(c).unwrap_or(0))).collect();
+    if cpu.select != CoreSelect::Any {
+        let want_big = cpu.select == CoreSelect::Big;
+        let picked: Vec<(usize, u64)> =
+            candidates.iter().copied().filter(|&(_, khz)| khz > 0 && is_big_core(khz) == want_big).collect();
+        if picked.is_empty() {
+            let kind = if want_big { "big" } else { "little" };
+            errln("profile", &format!("no {} cores found; using any core", kind));
+        } else {
+            candidates = picked;
+        }
+        if want_big {
+            candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        } else {
+            candidates.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        }
+    }
+
+    let count = if cpu.cores == 0 { candidates.len() } else { cpu.cores.min(candidates.len()) };
+    let mut chosen: Vec<usize> = candidates.into_iter().take(count).map(|(c, _)| c).collect();
+    chosen.sort_unstable();
+    Ok(chosen)
+}
+
 pub fn cmd() -> (f64, f64) {
     let cores = read_cpu_cores();
 
@@ -99,4 +183,22 @@ pub fn cmd() -> (f64, f64) {
     let (mcu, scu) = compute_onyx_units(&cores);
 
     (mcu, scu)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_lists() {
+        assert_eq!(parse_core_list("0"), Some(vec![0]));
+        assert_eq!(parse_core_list("0-3\n"), Some(vec![0, 1, 2, 3]));
+        assert_eq!(parse_core_list("0,2,4-5"), Some(vec![0, 2, 4, 5]));
+        assert_eq!(parse_core_list(""), Some(vec![]));
+
+        assert_eq!(parse_core_list("3-1"), None);
+        assert_eq!(parse_core_list("a"), None);
+        assert_eq!(parse_core_list("1-"), None);
+        assert_eq!(parse_core_list("-1"), None);
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::helpers::{errln, BLUE, BLUEB, DIM, ESC, GREEN, ONYX_DIR, RED, YELLOW};
+use crate::layer;
+
+//=== delta diff ===//
+// compares a user's upper layer (ONYX_DIR/delta/<uid>/<box>/upper) against
+// the box's base layers, so a delta can be reviewed before apply-delta
+// merges it for good.
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub path: String,
+    pub change: Change,
+    /// file, dir, symlink, device, fifo or socket
+    pub kind: &'static str,
+    /// unified diff for text files, only with --content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    user: &'a str,
+    uid: &'a str,
+    #[serde(rename = "box")]
+    box_name: &'a str,
+    added: usize,
+    modified: usize,
+    deleted: usize,
+    entries: &'a [Entry],
+}
+
+#[derive(Debug, Default)]
+pub struct DiffOpts {
+    pub json: bool,
+    pub summary: bool,
+    pub content: bool,
+}
+
+fn kind_of(meta: &fs::Metadata) -> &'static str {
+    let ft = meta.file_type();
+    if ft.is_dir() {
+        "dir"
+    } else if ft.is_symlink() {
+        "symlink"
+    } else if ft.is_char_device() || ft.is_block_device() {
+        "device"
+    } else if ft.is_fifo() {
+        "fifo"
+    } else if ft.is_socket() {
+        "socket"
+    } else {
+        "file"
+    }
+}
+
+/// names visible in the base view of directory `rel`
+fn base_children(layers: &[PathBuf], rel: &Path) -> BTreeSet<OsString> {
+    let mut names = BTreeSet::new();
+    for l in layers {
+        let Ok(entries) = fs::read_dir(l.join(rel)) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            if names.contains(&name) {
+                continue;
+            }
+            if layer::find(layers, &rel.join(&name)).is_some() {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut fa = fs::File::open(a)?;
+    let mut fb = fs::File::open(b)?;
+    let mut ba = [0u8; 8192];
+    let mut bb = [0u8; 8192];
+
+    loop {
+        let n = fa.read(&mut ba)?;
+        if n == 0 {
+            return Ok(fb.read(&mut bb)? == 0);
+        }
+        fb.read_exact(&mut bb[..n]).map_err(|_| io::Error::other("short read"))?;
+        if ba[..n] != bb[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// did the upper copy of an entry actually change anything?
+/// overlay copies dirs (and files on chmod) up, so presence alone isn't enough.
+fn changed(upper: &Path, um: &fs::Metadata, base: &Path, bm: &fs::Metadata) -> bool {
+    if kind_of(um) != kind_of(bm) || um.mode() != bm.mode() || um.uid() != bm.uid() || um.gid() != bm.gid() {
+        return true;
+    }
+
+    let ft = um.file_type();
+    if ft.is_symlink() {
+        return fs::read_link(upper).ok() != fs::read_link(base).ok();
+    }
+    if ft.is_char_device() || ft.is_block_device() {
+        return um.rdev() != bm.rdev();
+    }
+    if ft.is_file() {
+        return um.len() != bm.len() || !same_content(upper, base).unwrap_or(false);
+    }
+    false
+}
+
+fn is_text(path: &Path) -> bool {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return false;
+    };
+    if !meta.is_file() {
+        return false;
+    }
+    let mut head = Vec::with_capacity(8192);
+    match fs::File::open(path) {
+        Ok(f) => {
+            if f.take(8192).read_to_end(&mut head).is_err() {
+                return false;
+            }
+        }
+        Err(_) => return false,
+    }
+    !head.contains(&0)
+}
+
+/// `diff -u` between two text files; a missing side is read as empty
+fn content_diff(old: Option<&Path>, new: Option<&Path>, rel: &str) -> Option<String> {
+    let null = Path::new("/dev/null");
+    let old = old.filter(|p| is_text(p));
+    let new = new.filter(|p| is_text(p));
+    if old.is_none() && new.is_none() {
+        return None;
+    }
+
+    let out = Command::new("diff")
+        .arg("-u")
+        .arg("--label")
+        .arg(format!("a/{}", rel))
+        .arg("--label")
+        .arg(format!("b/{}", rel))
+        .arg(old.unwrap_or(null))
+        .arg(new.unwrap_or(null))
+        .output()
+        .ok()?;
+
+    let text = String::from_utf8_lossy(&out.stdout).to_string();
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// can `diff` be run at all? minimal hosts often come without diffutils
+fn have_diff() -> bool {
+    match Command::new("diff").arg("--version").output() {
+        Ok(_) => true,
+        Err(e) => {
+            errln("box", &format!("--content needs diff(1), which couldn't be run ({}); listing paths only", e));
+            false
+        }
+    }
+}
+
+/// everything `upper` adds, modifies or deletes relative to `layers`
+pub fn collect(upper: &Path, layers: &[PathBuf], content: bool) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let content = content && have_diff();
+
+    let mut push = |rel: &Path, change: Change, kind: &'static str, old: Option<&Path>, new: Option<&Path>| {
+        let path = format!("/{}", rel.display());
+        let diff = if content { content_diff(old, new, &rel.to_string_lossy()) } else { None };
+        entries.push(Entry { path, change, kind, diff });
+    };
+
+    for entry in WalkDir::new(upper).follow_links(false).sort_by_file_name().min_depth(1) {
+        let entry = entry.map_err(io::Error::other)?;
+        let path = entry.path();
+        let rel = path.strip_prefix(upper).map_err(io::Error::other)?;
+        let meta = entry.metadata().map_err(io::Error::other)?;
+
+        if path.file_name().is_some_and(|n| n == layer::OPAQUE_MARKER) {
+            continue;
+        }
+
+        // deletions: only worth reporting if the base actually has the path
+        if let Some(target) = layer::whiteout_target(path, &meta) {
+            let target_rel = target.strip_prefix(upper).map_err(io::Error::other)?;
+            if let Some(base) = layer::find(layers, target_rel) {
+                let bm = fs::symlink_metadata(&base)?;
+                push(target_rel, Change::Deleted, kind_of(&bm), Some(&base), None);
+            }
+            continue;
+        }
+
+        match layer::find(layers, rel) {
+            None => push(rel, Change::Added, kind_of(&meta), None, Some(path)),
+            Some(base) => {
+                let bm = fs::symlink_metadata(&base)?;
+                if changed(path, &meta, &base, &bm) {
+                    push(rel, Change::Modified, kind_of(&meta), Some(&base), Some(path));
+                }
+
+                // an opaque dir drops whatever the base had inside it
+                if meta.is_dir() && bm.is_dir() && layer::is_opaque(path) {
+                    for name in base_children(layers, rel) {
+                        if path.join(&name).symlink_metadata().is_ok() {
+                            continue;
+                        }
+                        let child_rel = rel.join(&name);
+                        if let Some(child) = layer::find(layers, &child_rel) {
+                            let cm = fs::symlink_metadata(&child)?;
+                            push(&child_rel, Change::Deleted, kind_of(&cm), Some(&child), None);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// print the diff of `uid`'s delta for `box_name`
+pub fn show(user: &str, uid: &str, box_name: &str, opts: &DiffOpts) -> io::Result<()> {
+    if !ONYX_DIR.join("sys").join(box_name).exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("box '{}' does not exist", box_name)));
+    }
+
+    let upper = ONYX_DIR.join("delta").join(uid).join(box_name).join("upper");
+    if !upper.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no delta found for user {} at {}", user, upper.display())));
+    }
+
+    let layers = crate::manifest::layers(box_name);
+    let entries = collect(&upper, &layers, opts.content)?;
+
+    let count = |c: Change| entries.iter().filter(|e| e.change == c).count();
+    let (added, modified, deleted) = (count(Change::Added), count(Change::Modified), count(Change::Deleted));
+
+    if opts.json {
+        let report = Report { user, uid, box_name, added, modified, deleted, entries: &entries };
+        let out = serde_json::to_string_pretty(&report).map_err(io::Error::other)?;
+        println!("{}", out);
+        return Ok(());
+    }
+
+    println!("{BLUEB}[>== diff: {} -> {} ==<]{ESC}", user, box_name);
+
+    if !opts.summary {
+        if entries.is_empty() {
+            println!("    {DIM}no changes{ESC}");
+        }
+        for e in &entries {
+            let (mark, color) = match e.change {
+                Change::Added => ("A", GREEN),
+                Change::Modified => ("M", YELLOW),
+                Change::Deleted => ("D", RED),
+            };
+            let slash = if e.kind == "dir" { "/" } else { "" };
+            println!("    {color}{}{ESC}  {}{}", mark, e.path, slash);
+            if let Some(d) = &e.diff {
+                for line in d.lines() {
+                    println!("        {DIM}{}{ESC}", line);
+                }
+            }
+        }
+    }
+
+    println!(
+        "{BLUE}[summary]{ESC} {GREEN}{} added{ESC}, {YELLOW}{} modified{ESC}, {RED}{} deleted{ESC}",
+        added, modified, deleted
+    );
+    Ok(())
+}
@@ -267,6 +267,15 @@ pub fn cmd() -> (bool, i32, bool, bool, bool, bool, String, String, bool) {
         println!("    {YELLOW}[root]{ESC} non-root user, using proot");
     }
 
+    // how profiles get enforced on this host
+    match crate::cgroup::delegated() {
+        Ok(base) => println!("    {GREEN}[limits]{ESC} cgroup v2, sessions go under {}", base.join("onyx").display()),
+        Err(why) => {
+            println!("    {YELLOW}[limits]{ESC} rlimit fallback ({}); memory limits cap address space", why);
+            println!("    {YELLOW}[limits]{ESC} procs has no effect here, except for root sessions run with --user=");
+        }
+    }
+
     if box64 && arch == "aarch64" {
         println!("    {GREEN}[box64]{ESC} installed");
     } else if !box64 && arch == "aarch64" {
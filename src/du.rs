@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use nix::unistd::{Uid, User};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::helpers::{errln, BLUE, BLUEB, DIM, ESC, ONYX_DIR, YELLOW};
+
+//=== disk usage ===//
+// where the space under ONYX_DIR goes: box bases, per-user deltas,
+// snapshots, proot's tmp dir and downloaded components. sizes are what's
+// allocated on disk, with hardlinks counted once per entry.
+
+#[derive(Debug, Serialize)]
+struct Usage {
+    /// base, delta, snapshots, tmp or component
+    kind: &'static str,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    bytes: u64,
+    /// the box this belongs to no longer exists
+    orphaned: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    root: String,
+    total: u64,
+    entries: &'a [Usage],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sort {
+    Size,
+    Name,
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    let mut seen = HashSet::new();
+    let mut total = 0;
+
+    for entry in WalkDir::new(path).follow_links(false).into_iter().filter_map(Result::ok) {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.nlink() > 1 && !meta.is_dir() && !seen.insert((meta.dev(), meta.ino())) {
+            continue;
+        }
+        total += meta.blocks() * 512;
+    }
+
+    total
+}
+
+fn human(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", size, units[unit]) }
+}
+
+fn user_name(uid: &str) -> Option<String> {
+    let raw = uid.parse::<u32>().ok()?;
+    User::from_uid(Uid::from_raw(raw)).ok().flatten().map(|u| u.name)
+}
+
+fn subdirs(path: &Path) -> Vec<String> {
+    let mut out: Vec<String> = fs::read_dir(path)
+        .map(|it| {
+            it.filter_map(Result::ok)
+                .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    out.sort();
+    out
+}
+
+/// per-user, per-box dirs laid out as <root>/<uid>/<box>
+fn per_user(kind: &'static str, root: &Path, out: &mut Vec<Usage>) {
+    for uid in subdirs(root) {
+        for box_name in subdirs(&root.join(&uid)) {
+            out.push(Usage {
+                kind,
+                orphaned: !ONYX_DIR.join("sys").join(&box_name).exists(),
+                bytes: disk_usage(&root.join(&uid).join(&box_name)),
+                user: user_name(&uid),
+                uid: Some(uid.clone()),
+                name: box_name,
+            });
+        }
+    }
+}
+
+fn collect() -> Vec<Usage> {
+    let mut out = Vec::new();
+
+    for name in subdirs(&ONYX_DIR.join("sys")) {
+        out.push(Usage {
+            kind: "base",
+            bytes: disk_usage(&ONYX_DIR.join("sys").join(&name)),
+            name,
+            uid: None,
+            user: None,
+            orphaned: false,
+        });
+    }
+
+    per_user("delta", &ONYX_DIR.join("delta"), &mut out);
+    per_user("snapshots", &ONYX_DIR.join("snapshots"), &mut out);
+
+    out.push(Usage {
+        kind: "tmp",
+        name: "tmp".to_string(),
+        bytes: disk_usage(&ONYX_DIR.join("tmp")),
+        uid: None,
+        user: None,
+        orphaned: false,
+    });
+
+    for comp in ["bin", "glibc", "box64"] {
+        let path = ONYX_DIR.join(comp);
+        if !path.exists() {
+            continue;
+        }
+        out.push(Usage {
+            kind: "component",
+            name: comp.to_string(),
+            bytes: disk_usage(&path),
+            uid: None,
+            user: None,
+            orphaned: false,
+        });
+    }
+
+    out
+}
+
+fn owner(u: &Usage) -> String {
+    match (&u.user, &u.uid) {
+        (Some(name), _) => name.clone(),
+        (None, Some(uid)) => format!("uid {}", uid),
+        _ => String::new(),
+    }
+}
+
+pub fn cmd(args: Vec<String>) {
+    let json = args.iter().any(|a| a == "--json");
+    let sort = match args.iter().find_map(|a| a.strip_prefix("--sort=")) {
+        None | Some("size") => Sort::Size,
+        Some("name") => Sort::Name,
+        Some(other) => {
+            errln("du", &format!("unknown sort '{}' (use size or name)", other));
+            std::process::exit(1);
+        }
+    };
+
+    let mut entries = collect();
+    match sort {
+        Sort::Size => entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name))),
+        Sort::Name => entries.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.uid.cmp(&b.uid))),
+    }
+    let total: u64 = entries.iter().map(|u| u.bytes).sum();
+
+    if json {
+        let report = Report { root: ONYX_DIR.display().to_string(), total, entries: &entries };
+        match serde_json::to_string_pretty(&report) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                errln("du", &format!("failed to encode report: {}", e));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    println!("{BLUEB}[>== disk usage: {} ==<]{ESC}", ONYX_DIR.display());
+
+    let sections = [
+        ("base", "bases"),
+        ("delta", "deltas"),
+        ("snapshots", "snapshots"),
+        ("tmp", "temp"),
+        ("component", "components"),
+    ];
+
+    for (kind, title) in sections {
+        let rows: Vec<&Usage> = entries.iter().filter(|u| u.kind == kind).collect();
+        let sum: u64 = rows.iter().map(|u| u.bytes).sum();
+
+        println!("{BLUEB}{}:{ESC} {DIM}{}{ESC}", title, human(sum));
+        if rows.is_empty() {
+            println!("    {DIM}none{ESC}");
+            continue;
+        }
+
+        for u in rows {
+            let who = owner(u);
+            let label = if who.is_empty() { u.name.clone() } else { format!("{} ({})", u.name, who) };
+            let orphan = if u.orphaned { format!(" {YELLOW}[orphaned]{ESC}") } else { String::new() };
+            println!("    {BLUE}{:>10}{ESC}  {}{}", human(u.bytes), label, orphan);
+        }
+    }
+
+    println!("{BLUE}[total]{ESC} {}", human(total));
+}
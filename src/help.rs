@@ -49,20 +49,57 @@ fn sub_cmd(args: Vec<String>) {
             let r#box = vec![
                 ("delete <name>".to_string(), "Delete an existing Onyx box".to_string()),
 
-                ("open <name>\n --profile=PROFILE".to_string(), 
+                ("open <name>\n --profile=PROFILE --bind=HOST:GUEST[:ro]\n --env=K=V --env-file=PATH --pass-env=NAME,...\n --user=NAME|UID[:GID] --workdir=PATH\n --backend=auto|chroot|userns|proot|fuse-proot\n --isolate=pid,uts,ipc --net=host|none|loopback".to_string(), 
                 "Open an Onyx box in the terminal".to_string()),
 
-                ("exec <name>\n --profile=PROFILE".to_string(), 
-                "Execute a single command within the Onyx box".to_string()),
+                ("exec <name> [options] -- <argv...>\n --profile=PROFILE --bind=HOST:GUEST[:ro]\n --env=K=V --env-file=PATH --pass-env=NAME,...\n --user=NAME|UID[:GID] --workdir=PATH --shell\n --backend=auto|chroot|userns|proot|fuse-proot\n --isolate=pid,uts,ipc --net=host|none|loopback".to_string(), 
+                "Execute a single command within the Onyx box (--shell: run it as one shell string)".to_string()),
                 
-                ("create <name> <rootfs-folder>\n <move: TRUE/FALSE>".to_string(), 
+                ("create <name> <rootfs-folder>\n <move: TRUE/FALSE>\n --copy=full|reflink|hardlink".to_string(), 
                 "Create a new Onyx box from an existing rootfs".to_string()),
 
+                ("create <name> --from=PARENT".to_string(),
+                "Create a thin Onyx box layered on top of an existing box".to_string()),
+
+                ("rename <old> <new>".to_string(),
+                "Rename a box along with every user's delta and snapshots".to_string()),
+
+                ("duplicate <name> <new>\n --with-delta=USER".to_string(),
+                "Copy a box (and optionally a user's delta) into a new box".to_string()),
+
+                ("import <name> <archive>".to_string(),
+                "Create a new Onyx box from a .tar/.tar.zst/.tar.gz/.tar.xz rootfs".to_string()),
+
+                ("export <name> <out.tar.zst>\n --with-delta=USER".to_string(),
+                "Export an Onyx box (and optionally a user's delta) to an archive".to_string()),
+
+                ("config <name>\n --shell=PATH --profile=PROFILE --hostname=NAME\n --env=K=V --unset-env=K\n --env-file=PATH --pass-env=NAME,... --unpass-env=NAME\n --bind=HOST:GUEST[:ro] --unbind=GUEST".to_string(),
+                "Show or change the settings stored in a box's box.toml".to_string()),
+
+                ("snapshot <name> <tag>\n --user=USER".to_string(),
+                "Save the current delta of a box as a named restore point".to_string()),
+
+                ("snapshots <name>\n --user=USER".to_string(),
+                "List the saved snapshots of a box's delta".to_string()),
+
+                ("rollback <name> <tag>\n --user=USER".to_string(),
+                "Restore a box's delta to a saved snapshot".to_string()),
+
+                ("apply-delta <user> <name>\n --dry-run --resume --rollback".to_string(),
+                "Merge a user's delta into the box for good".to_string()),
+
+                ("reset <name>\n --user=USER --path=PATH".to_string(),
+                "Discard a user's delta (or part of it) without merging".to_string()),
+
+                ("diff <user> <name>\n --summary --json --content".to_string(),
+                "Show what a user's delta adds, changes and deletes".to_string()),
+
                 ("list".to_string(), "List all existing Onyx boxes".to_string()),
             ];
             make_help("Box Modules:", r#box);
             println!();
             infoln("help", "--profile=PROFILE is optional, see 'onyx help profile' for info");
+            infoln("help", "exec exits with the command's status (128+N if killed by signal N); 125 = onyx failed, 126 = command can't run, 127 = command not found");
         }
         "update" => {
             let update = vec![
@@ -80,10 +117,10 @@ fn sub_cmd(args: Vec<String>) {
                 ("list".to_string(), "List all available performance profiles".to_string()),
                 ("use <profile>".to_string(), "Use a specific performance profile".to_string()),
 
-                ("edit <profile> \n--name=NAME --description=DESCRIPTION --mem=TYPE:VALUE --nice=NICENESS --cores=CPU_CORES".to_string(), 
+                ("edit <profile> \n--description=DESCRIPTION --memory=TYPE:VALUE --nice=NICENESS --cpu-cores=N\n--cpu-set=LIST --cpu-select=big|little|any --cpu-quota=PERCENT\n--io-class=best-effort|idle|realtime --io-level=0-7 --io-weight=N\n--io-read=MBPS --io-write=MBPS --sched=normal|batch|idle\n--procs=N --files=N --cpu-time=DURATION --timeout=DURATION\n--oom-score-adj=-1000..1000".to_string(), 
                 "Edit an existing performance profile".to_string()),
 
-                ("create <profile>\n--name=NAME --description=DESCRIPTION --mem=TYPE:VALUE --nice=NICENESS --cores=CPU_CORES".to_string(), 
+                ("create <profile>\n--description=DESCRIPTION --memory=TYPE:VALUE --nice=NICENESS --cpu-cores=N\n--cpu-set=LIST --cpu-select=big|little|any --cpu-quota=PERCENT\n--io-class=best-effort|idle|realtime --io-level=0-7 --io-weight=N\n--io-read=MBPS --io-write=MBPS --sched=normal|batch|idle\n--procs=N --files=N --cpu-time=DURATION --timeout=DURATION\n--oom-score-adj=-1000..1000".to_string(), 
                 "Create your own performance profile".to_string()),
 
                 ("delete <profile>".to_string(), "Delete a performance profile".to_string()),
@@ -107,6 +144,12 @@ fn sub_cmd(args: Vec<String>) {
             println!();
             println!("Gives a system diagnostic.");
         }
+        "du" => {
+            println!("{BLUE}usage:{ESC}");
+            println!("  onyx du [--sort=size|name] [--json]");
+            println!();
+            println!("Shows disk usage of box bases, user deltas, snapshots, temp data and components.");
+        }
         "help" => {
             println!("{BLUE}usage:{ESC}");
             println!("  onyx help <module>");
@@ -144,6 +187,7 @@ pub fn cmd(args: Vec<String>) {
         ("profile".to_string(), "Set performance profiles for Onyx boxes".to_string()),
         ("normalize".to_string(), "Fix Onyx file permissions for security".to_string()),
         ("doctor".to_string(), "Diagnose Onyx installation".to_string()),
+        ("du".to_string(), "Show what is using disk space under the Onyx dir".to_string()),
         ("help".to_string(), "Show this help message".to_string()),
         // ("lux", "Manage Onyx extensions and plugins"),
     ];
@@ -51,7 +51,7 @@ pub static ONYX_DIR: Lazy<PathBuf> = Lazy::new(|| {
     }
 
     // create standard subfolders
-    for folder in &["bin/core", "glibc", "box64", "sys", "tmp", "profiles"] {
+    for folder in &["bin/core", "glibc", "box64", "sys", "tmp", "profiles", "meta"] {
         let sub = p.join(folder);
         if let Err(e) = fs::create_dir_all(&sub) {
             errln("onyx", &format!("failed to create {}: {}", sub.display(), e));
@@ -123,6 +123,32 @@ pub fn set_nice(nice_level: i32) -> io::Result<()> {
     }
 }
 
+/// ioprio_set(2) for the current process; children inherit it.
+/// `class`: 1 realtime, 2 best-effort, 3 idle; `level`: 0 (first) to 7
+pub fn set_io_priority(class: u32, level: u32) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+    let prio = (class << IOPRIO_CLASS_SHIFT) | level;
+    let res = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio as libc::c_int) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// switch the current process to a non-realtime scheduling policy
+/// (SCHED_OTHER, SCHED_BATCH or SCHED_IDLE); children inherit it
+pub fn set_sched_policy(policy: libc::c_int) -> io::Result<()> {
+    let param = libc::sched_param { sched_priority: 0 };
+    let res = unsafe { libc::sched_setscheduler(0, policy, &param) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
 pub fn set_memory_limit(bytes: u64) -> Result<(), std::io::Error> {
     let limit = rlimit {
         rlim_cur: bytes,
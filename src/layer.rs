@@ -0,0 +1,244 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+//=== overlay layer semantics ===//
+// an upper layer (kernel overlayfs or fuse-overlayfs) marks deletions and
+// replaced directories in two styles:
+//   - whiteouts: a 0/0 char device, or an aufs-style `.wh.<name>` file
+//   - opaque dirs: an `overlay.opaque` xattr, or a `.wh..wh..opq` file inside
+
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+const OPAQUE_XATTRS: [&str; 3] = [
+    "trusted.overlay.opaque",
+    "user.overlay.opaque",
+    "user.fuseoverlayfs.opaque",
+];
+
+/// xattrs that only mean something to the overlay driver itself
+pub fn is_overlay_xattr(name: &str) -> bool {
+    name.starts_with("trusted.overlay.")
+        || name.starts_with("user.overlay.")
+        || name.starts_with("user.fuseoverlayfs.")
+}
+
+/// true for anything that is layer bookkeeping rather than guest content
+pub fn is_whiteout(path: &Path, meta: &fs::Metadata) -> bool {
+    if meta.file_type().is_char_device() && meta.rdev() == 0 {
+        return true;
+    }
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.starts_with(WHITEOUT_PREFIX))
+        .unwrap_or(false)
+}
+
+/// the path a whiteout deletes, or None if `path` isn't a whiteout
+/// (the opaque marker isn't one either: it hides a dir's lower contents, not a path)
+pub fn whiteout_target(path: &Path, meta: &fs::Metadata) -> Option<PathBuf> {
+    if meta.file_type().is_char_device() && meta.rdev() == 0 {
+        return Some(path.to_path_buf());
+    }
+    let name = path.file_name()?.to_str()?;
+    if name == OPAQUE_MARKER {
+        return None;
+    }
+    let target = name.strip_prefix(WHITEOUT_PREFIX)?;
+    Some(path.with_file_name(target))
+}
+
+/// does this directory hide everything below it in lower layers?
+pub fn is_opaque(dir: &Path) -> bool {
+    for name in OPAQUE_XATTRS {
+        if let Ok(Some(v)) = xattr::get(dir, name)
+            && v.as_slice() == b"y"
+        {
+            return true;
+        }
+    }
+    dir.join(OPAQUE_MARKER).symlink_metadata().is_ok()
+}
+
+/// what a higher layer says about `rel` in the layers below it
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    /// the layer doesn't touch this path
+    Passthrough,
+    /// the layer provides its own entry for this path
+    Replaced { is_dir: bool },
+    /// the layer deletes this path (or an ancestor of it)
+    Hidden,
+}
+
+/// check a single higher `layer` for the relative path `rel`
+pub fn lookup(layer: &Path, rel: &Path) -> Lookup {
+    let parts: Vec<_> = rel.components().collect();
+    let mut cur = layer.to_path_buf();
+
+    for (i, part) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+
+        // aufs-style whiteout sitting next to the entry
+        let name = part.as_os_str().to_string_lossy();
+        if cur.join(format!("{}{}", WHITEOUT_PREFIX, name)).symlink_metadata().is_ok() {
+            return Lookup::Hidden;
+        }
+
+        cur.push(part);
+        let meta = match fs::symlink_metadata(&cur) {
+            Ok(m) => m,
+            Err(_) => return Lookup::Passthrough,
+        };
+
+        if is_whiteout(&cur, &meta) {
+            return Lookup::Hidden;
+        }
+
+        if last {
+            return Lookup::Replaced { is_dir: meta.is_dir() };
+        }
+
+        // a file (or opaque dir) on the way down shadows the whole subtree
+        if !meta.is_dir() || is_opaque(&cur) {
+            return Lookup::Hidden;
+        }
+    }
+
+    Lookup::Passthrough
+}
+
+/// where `rel` comes from in the merged view of `layers` (top to bottom), if anywhere
+pub fn find(layers: &[PathBuf], rel: &Path) -> Option<PathBuf> {
+    for l in layers {
+        match lookup(l, rel) {
+            Lookup::Passthrough => {}
+            Lookup::Replaced { .. } => return Some(l.join(rel)),
+            Lookup::Hidden => return None,
+        }
+    }
+    None
+}
+
+/// walk the merged view of `layers` (ordered top to bottom) and call `f`
+/// once for every visible entry, with the layer that provides it.
+/// entries are visited bottom layer first; whiteouts are never passed on.
+pub fn flatten<F>(layers: &[PathBuf], mut f: F) -> io::Result<()>
+where
+    F: FnMut(&Path, &Path, &fs::Metadata) -> io::Result<()>,
+{
+    for (depth, layer) in layers.iter().enumerate().rev() {
+        let higher = &layers[..depth];
+
+        let mut it = WalkDir::new(layer)
+            .follow_links(false)
+            .sort_by_file_name()
+            .min_depth(1)
+            .into_iter();
+
+        while let Some(entry) = it.next() {
+            let entry = entry.map_err(io::Error::other)?;
+            let path = entry.path();
+            let rel = path.strip_prefix(layer).map_err(io::Error::other)?;
+            let meta = entry.metadata().map_err(io::Error::other)?;
+
+            if is_whiteout(path, &meta) {
+                continue;
+            }
+
+            let mut visible = true;
+            let mut descend = true;
+            for h in higher {
+                match lookup(h, rel) {
+                    Lookup::Passthrough => {}
+                    Lookup::Replaced { is_dir } => {
+                        visible = false;
+                        // a plain dir above merges with ours, so children still count
+                        descend = is_dir && meta.is_dir() && !is_opaque(&h.join(rel));
+                    }
+                    Lookup::Hidden => {
+                        visible = false;
+                        descend = false;
+                    }
+                }
+                if !visible {
+                    break;
+                }
+            }
+
+            if meta.is_dir() && !descend {
+                it.skip_current_dir();
+            }
+
+            if visible {
+                f(rel, path, &meta)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for d in ["etc/conf.d", "opq", "usr"] {
+            fs::create_dir_all(root.join(d)).unwrap();
+        }
+        fs::write(root.join("etc/hosts"), "").unwrap();
+        fs::write(root.join("etc/.wh.motd"), "").unwrap();
+        fs::write(root.join(".wh.var"), "").unwrap();
+        fs::write(root.join(format!("opq/{}", OPAQUE_MARKER)), "").unwrap();
+        fs::write(root.join("bin"), "a file over a lower dir").unwrap();
+        dir
+    }
+
+    #[test]
+    fn lookups() {
+        let dir = layer();
+        let l = dir.path();
+        let lookup = |rel: &str| super::lookup(l, Path::new(rel));
+
+        assert_eq!(lookup("etc/hosts"), Lookup::Replaced { is_dir: false });
+        assert_eq!(lookup("etc/conf.d"), Lookup::Replaced { is_dir: true });
+        assert_eq!(lookup("etc/passwd"), Lookup::Passthrough);
+        assert_eq!(lookup("usr/lib/x"), Lookup::Passthrough);
+        assert_eq!(lookup("home/user"), Lookup::Passthrough);
+
+        // whiteouts hide the path and everything below it
+        assert_eq!(lookup("etc/motd"), Lookup::Hidden);
+        assert_eq!(lookup("var"), Lookup::Hidden);
+        assert_eq!(lookup("var/log/syslog"), Lookup::Hidden);
+
+        // so do opaque dirs and files on the way down
+        assert_eq!(lookup("opq"), Lookup::Replaced { is_dir: true });
+        assert_eq!(lookup("opq/old"), Lookup::Hidden);
+        assert_eq!(lookup("bin/sh"), Lookup::Hidden);
+    }
+
+    #[test]
+    fn find_goes_top_down() {
+        let (top, bottom) = (layer(), tempfile::tempdir().unwrap());
+        for f in ["etc/passwd", "etc/motd", "opq/old", "usr/bin/env"] {
+            let path = bottom.path().join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let layers = [top.path().to_path_buf(), bottom.path().to_path_buf()];
+
+        assert_eq!(find(&layers, Path::new("etc/hosts")), Some(top.path().join("etc/hosts")));
+        assert_eq!(find(&layers, Path::new("etc/passwd")), Some(bottom.path().join("etc/passwd")));
+        assert_eq!(find(&layers, Path::new("usr/bin/env")), Some(bottom.path().join("usr/bin/env")));
+        assert_eq!(find(&layers, Path::new("etc/motd")), None);
+        assert_eq!(find(&layers, Path::new("opq/old")), None);
+        assert_eq!(find(&layers, Path::new("nope")), None);
+    }
+}
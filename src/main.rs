@@ -1,12 +1,23 @@
 mod lux;
 mod normalize;
 mod r#box;
+mod backend;
+mod cgroup;
+mod commit;
+mod copy;
+mod diff;
+mod du;
 mod helpers;
+mod layer;
+mod manifest;
 mod doctor;
 mod help;
 mod update;
 mod cpu;
 mod profile;
+mod session;
+mod snapshot;
+mod tar;
 
 use crate::helpers::{ONYX_DIR, check_file_authority};
 use crate::helpers::{errln, infoln};
@@ -33,6 +44,9 @@ fn main() {
         "doctor" => {
             let _ = doctor::cmd();
         }
+        "du" => {
+            du::cmd(args);
+        }
         "update" => {
             update::cmd(args);
         }
@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::helpers::ONYX_DIR;
+
+//=== box manifest ===//
+// every box gets ONYX_DIR/meta/<name>/box.toml next to its rootfs in
+// ONYX_DIR/sys/<name>. it records where the box came from and keeps
+// per-box settings so they don't have to be passed on every run.
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct BoxManifest {
+    pub name: String,
+    pub created: String,
+    /// how the box was made, e.g. "copy:/path/to/rootfs" or "import:debian.tar.zst"
+    pub source: String,
+    pub arch: Option<String>,
+    pub distro: Option<String>,
+    pub shell: Option<String>,
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// host variables handed through to the guest; "LC_*" style prefixes work
+    #[serde(default)]
+    pub pass_env: Vec<String>,
+    /// "<host>:<guest>" bind mounts applied on every open/exec
+    #[serde(default)]
+    pub binds: Vec<String>,
+    /// hostname under `--isolate=uts`; the box's name if unset
+    pub hostname: Option<String>,
+    /// derived boxes only hold their own layer; the parent's rootfs sits below it
+    pub parent: Option<String>,
+    /// "uid:gid" owning every file in the box's own layer, or "mixed".
+    /// worked out whenever the layer is written, so launches needn't walk it
+    pub owner: Option<String>,
+}
+
+pub fn meta_dir(name: &str) -> PathBuf {
+    ONYX_DIR.join("meta").join(name)
+}
+
+pub fn manifest_path(name: &str) -> PathBuf {
+    meta_dir(name).join("box.toml")
+}
+
+/// loads a box manifest, None for boxes made before manifests existed
+pub fn load(name: &str) -> Option<BoxManifest> {
+    let s = fs::read_to_string(manifest_path(name)).ok()?;
+    toml::from_str(&s).ok()
+}
+
+pub fn save(manifest: &BoxManifest) -> io::Result<()> {
+    fs::create_dir_all(meta_dir(&manifest.name))?;
+    let toml_str = toml::to_string_pretty(manifest)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(manifest_path(&manifest.name), toml_str)
+}
+
+pub fn remove(name: &str) -> io::Result<()> {
+    let dir = meta_dir(name);
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// the rootfs layers of a box, top to bottom: its own dir in ONYX_DIR/sys,
+/// then its parent's, and so on for derived boxes
+pub fn layers(name: &str) -> Vec<PathBuf> {
+    let mut out = vec![ONYX_DIR.join("sys").join(name)];
+    let mut seen = vec![name.to_string()];
+
+    let mut cur = load(name).and_then(|m| m.parent);
+    while let Some(parent) = cur {
+        // a hand-edited box.toml could loop back on itself
+        if seen.contains(&parent) {
+            break;
+        }
+        out.push(ONYX_DIR.join("sys").join(&parent));
+        cur = load(&parent).and_then(|m| m.parent);
+        seen.push(parent);
+    }
+
+    out
+}
+
+/// boxes whose manifest names `name` as their parent
+pub fn children(name: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let Ok(entries) = fs::read_dir(ONYX_DIR.join("meta")) else {
+        return out;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let child = entry.file_name().to_string_lossy().to_string();
+        if let Some(m) = load(&child)
+            && m.parent.as_deref() == Some(name)
+        {
+            out.push(child);
+        }
+    }
+
+    out.sort();
+    out
+}
+
+//=== detection ===//
+/// resolve `rel` inside `root`, following symlinks without leaving the rootfs
+fn resolve_in_root(root: &Path, rel: &str) -> Option<PathBuf> {
+    let mut cur = root.join(rel.trim_start_matches('/'));
+    for _ in 0..16 {
+        let meta = fs::symlink_metadata(&cur).ok()?;
+        if !meta.file_type().is_symlink() {
+            return Some(cur);
+        }
+        let target = fs::read_link(&cur).ok()?;
+        cur = if target.is_absolute() {
+            root.join(target.strip_prefix("/").ok()?)
+        } else {
+            cur.parent()?.join(target)
+        };
+    }
+    None
+}
+
+/// "uid:gid" if one user owns everything under `root`, "mixed" otherwise
+pub fn detect_owner(root: &Path) -> String {
+    let mut ids = WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok()?.metadata().ok())
+        .map(|m| (m.uid(), m.gid()));
+
+    let Some(first) = ids.next() else {
+        return "mixed".to_string();
+    };
+    if ids.all(|id| id == first) {
+        format!("{}:{}", first.0, first.1)
+    } else {
+        "mixed".to_string()
+    }
+}
+
+/// guest architecture from the ELF header of the box's /bin/sh
+pub fn detect_arch(root: &Path) -> Option<String> {
+    let sh = resolve_in_root(root, "bin/sh")?;
+    let mut head = [0u8; 20];
+    fs::File::open(sh).ok()?.read_exact(&mut head).ok()?;
+
+    if &head[0..4] != b"\x7fELF" {
+        return None;
+    }
+
+    // e_machine, honoring the file's own endianness
+    let machine = if head[5] == 2 {
+        u16::from_be_bytes([head[18], head[19]])
+    } else {
+        u16::from_le_bytes([head[18], head[19]])
+    };
+
+    let arch = match machine {
+        0x03 => "x86",
+        0x3e => "x86_64",
+        0x28 => "arm",
+        0xb7 => "aarch64",
+        0xf3 => "riscv64",
+        0x08 => "mips",
+        0x14 | 0x15 => "powerpc",
+        0x16 => "s390x",
+        _ => return Some(format!("unknown (e_machine {:#x})", machine)),
+    };
+    Some(arch.to_string())
+}
+
+/// PRETTY_NAME (or NAME) from the box's os-release
+pub fn detect_distro(root: &Path) -> Option<String> {
+    let data = ["etc/os-release", "usr/lib/os-release"]
+        .iter()
+        .filter_map(|p| resolve_in_root(root, p))
+        .find_map(|p| fs::read_to_string(p).ok())?;
+
+    let field = |key: &str| {
+        data.lines()
+            .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+            .map(|v| v.trim().trim_matches('"').trim_matches('\'').to_string())
+    };
+
+    field("PRETTY_NAME").or_else(|| field("NAME"))
+}
+
+/// reads KEY=VALUE lines; blank lines, `#` comments and a leading
+/// `export ` are skipped, and one layer of matching quotes is stripped
+pub fn parse_env_file(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let data = fs::read_to_string(path)?;
+    let mut out = Vec::new();
+
+    for (n, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((k, v)) = line.split_once('=') else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: expected KEY=VALUE", path.display(), n + 1),
+            ));
+        };
+
+        let v = v.trim();
+        let v = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|(a, b)| v.strip_prefix(*a)?.strip_suffix(*b))
+            .unwrap_or(v);
+        out.push((k.trim().to_string(), v.to_string()));
+    }
+
+    Ok(out)
+}
+
+/// one line of a guest's /etc/passwd
+#[derive(Debug, Clone)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+    pub shell: String,
+}
+
+pub fn parse_passwd(data: &str) -> Vec<PasswdEntry> {
+    data.lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| {
+            let f: Vec<&str> = l.split(':').collect();
+            if f.len() < 7 {
+                return None;
+            }
+            Some(PasswdEntry {
+                name: f[0].to_string(),
+                uid: f[2].parse().ok()?,
+                gid: f[3].parse().ok()?,
+                home: f[5].to_string(),
+                shell: f[6].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// gids of the groups in a guest's /etc/group that list `user` as a member
+pub fn guest_groups(data: &str, user: &str) -> Vec<u32> {
+    data.lines()
+        .filter_map(|l| {
+            let f: Vec<&str> = l.split(':').collect();
+            if f.len() < 4 || !f[3].split(',').any(|m| m == user) {
+                return None;
+            }
+            f[2].parse().ok()
+        })
+        .collect()
+}
+
+/// a host dir (or file) made visible inside a box
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bind {
+    pub host: String,
+    pub guest: String,
+    pub ro: bool,
+}
+
+impl Bind {
+    /// back to the "<host>:<guest>[:ro]" form
+    pub fn spec(&self) -> String {
+        if self.ro {
+            format!("{}:{}:ro", self.host, self.guest)
+        } else {
+            format!("{}:{}", self.host, self.guest)
+        }
+    }
+}
+
+/// splits a "<host>:<guest>[:ro|:rw]" bind spec
+pub fn parse_bind(spec: &str) -> Option<Bind> {
+    let (rest, ro) = match spec.rsplit_once(':') {
+        Some((rest, "ro")) => (rest, true),
+        Some((rest, "rw")) => (rest, false),
+        _ => (spec, false),
+    };
+    let (host, guest) = rest.split_once(':')?;
+    if host.is_empty() || !guest.starts_with('/') || guest.contains(':') {
+        return None;
+    }
+    Some(Bind { host: host.to_string(), guest: guest.to_string(), ro })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(host: &str, guest: &str, ro: bool) -> Option<Bind> {
+        Some(Bind { host: host.to_string(), guest: guest.to_string(), ro })
+    }
+
+    #[test]
+    fn parse_bind_specs() {
+        assert_eq!(parse_bind("/srv:/mnt/srv"), bind("/srv", "/mnt/srv", false));
+        assert_eq!(parse_bind("/srv:/mnt/srv:ro"), bind("/srv", "/mnt/srv", true));
+        assert_eq!(parse_bind("/srv:/mnt/srv:rw"), bind("/srv", "/mnt/srv", false));
+        // the host side may be relative, the guest side may not
+        assert_eq!(parse_bind("data:/data"), bind("data", "/data", false));
+
+        assert_eq!(parse_bind("/srv"), None);
+        assert_eq!(parse_bind(":/mnt"), None);
+        assert_eq!(parse_bind("/srv:mnt"), None);
+        assert_eq!(parse_bind("/srv:/a:b"), None);
+        assert_eq!(parse_bind("/srv:/mnt:xx"), None);
+    }
+
+    #[test]
+    fn owner_is_mixed_once_anything_differs() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("etc")).unwrap();
+        fs::write(dir.path().join("etc/passwd"), "").unwrap();
+        let me = fs::metadata(dir.path()).unwrap();
+        assert_eq!(detect_owner(dir.path()), format!("{}:{}", me.uid(), me.gid()));
+
+        // only root can hand a file to someone else
+        if std::os::unix::fs::lchown(dir.path().join("etc/passwd"), Some(me.uid() + 1), None).is_ok() {
+            assert_eq!(detect_owner(dir.path()), "mixed");
+        }
+    }
+
+    #[test]
+    fn parse_env_file_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("env");
+        fs::write(
+            &path,
+            "# comment\n\nA=1\nexport B = two words \nC=\"quoted\"\nD='single'\nE=a=b\nF=\"unbalanced\n",
+        )
+        .unwrap();
+
+        let vars = parse_env_file(&path).unwrap();
+        let expect = [("A", "1"), ("B", "two words"), ("C", "quoted"), ("D", "single"), ("E", "a=b"), ("F", "\"unbalanced")];
+        assert_eq!(vars, expect.map(|(k, v)| (k.to_string(), v.to_string())));
+    }
+
+    #[test]
+    fn parse_env_file_reports_the_bad_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("env");
+        fs::write(&path, "A=1\nnot an assignment\n").unwrap();
+
+        let err = parse_env_file(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(":2: expected KEY=VALUE"), "{}", err);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::helpers::{errln, infoln, ONYX_DIR, pin_cpu, BLUE, ESC, BLUEB, GREEN, RED, YELLOW, BOLD, file_exists};
+use crate::helpers::{errln, infoln, ONYX_DIR, pin_cpu, set_io_priority, set_sched_policy, BLUE, ESC, BLUEB, GREEN, RED, YELLOW, BOLD, file_exists};
 use serde::{Serialize, Deserialize};
 use std::{
     collections::HashMap,
@@ -19,21 +19,25 @@ fn prof_table() {
     let name_w  = 12;
     let score_w = 8;
     let mem_w   = 10;
-    let cpu_w   = 8;
+    let cpu_w   = 14;
     let nice_w  = 4;
+    let io_w    = 14;
+    let sched_w = 6;
 
     // header
     println!(
-        "{BLUEB}{:<name_w$} {:<score_w$} {:<mem_w$} {:<cpu_w$} {:<nice_w$}{ESC}",
-        "name", "score", "memory", "cpu", "nice",
+        "{BLUEB}{:<name_w$} {:<score_w$} {:<mem_w$} {:<cpu_w$} {:<nice_w$} {:<io_w$} {:<sched_w$}{ESC}",
+        "name", "score", "memory", "cpu", "nice", "io", "sched",
         name_w = name_w,
         score_w = score_w,
         mem_w = mem_w,
         cpu_w = cpu_w,
         nice_w = nice_w,
+        io_w = io_w,
+        sched_w = sched_w,
     );
 
-    println!("{BOLD}{}{ESC}", "==".repeat(name_w + score_w + mem_w + cpu_w + nice_w + 5));
+    println!("{BOLD}{}{ESC}", "==".repeat(name_w + score_w + mem_w + cpu_w + nice_w + io_w + sched_w + 7));
 
     // rows
     for p in ordered {
@@ -45,7 +49,7 @@ fn prof_table() {
 
         let cpu_color = match &p.cpu {
             None => GREEN,
-            Some(cpu) if cpu.cores >= 2 => YELLOW,
+            Some(cpu) if cpu.count() == 0 || cpu.count() >= 2 => YELLOW,
             _ => RED,
         };
 
@@ -55,25 +59,48 @@ fn prof_table() {
             _      => RED,
         };
 
+        let io_color = match p.io_weight() {
+            0 => GREEN,
+            1..=200 => YELLOW,
+            _ => RED,
+        };
+
+        let sched_color = match p.sched.as_ref().map(|s| s.policy) {
+            None | Some(SchedPolicy::Normal) => GREEN,
+            Some(SchedPolicy::Batch) => YELLOW,
+            Some(SchedPolicy::Idle) => RED,
+        };
+
         println!(
             "{BLUEB}{:<name_w$}{ESC} \
             {BLUE}{:<score_w$}{ESC} \
             {mem_color}{:<mem_w$}{ESC} \
             {cpu_color}{:<cpu_w$}{ESC} \
-            {nice_color}{:<nice_w$}{ESC}    {}",
+            {nice_color}{:<nice_w$}{ESC} \
+            {io_color}{:<io_w$}{ESC} \
+            {sched_color}{:<sched_w$}{ESC}    {}",
             p.name,
             p.score(),
             p.memory_display(),
             p.cpu_display(),
             p.nice,
+            p.io_display(),
+            p.sched_display(),
             p.description.as_deref().unwrap_or(""),
             name_w = name_w,
             score_w = score_w,
             mem_w = mem_w,
             cpu_w = cpu_w,
             nice_w = nice_w,
+            io_w = io_w,
+            sched_w = sched_w,
         );
     }
+
+    // procs is the one limit the table doesn't show, so say when it won't hold
+    if profiles.values().any(|p| p.procs.is_some()) && crate::cgroup::delegated().is_err() {
+        println!("{YELLOW}note:{ESC} no cgroup v2 here, so procs has no effect (except for root sessions run with --user=)");
+    }
 }
 
 pub fn load_profiles(dir: &Path) -> std::io::Result<HashMap<String, Profile>> {
@@ -105,18 +132,50 @@ pub fn read_current_profile() -> Option<String> {
     std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
 }
 
-pub fn apply_profile_cpu(profile: &Profile) {
+/// pin onyx (and so the box) to the profile's cores.
+/// returns the cores the session can run on.
+pub fn apply_profile_cpu(profile: &Profile) -> Vec<usize> {
     if let Some(cpu) = &profile.cpu {
-        // pin to first N cores
-        let cores: Vec<usize> = (0..cpu.cores).collect();
-        if let Err(e) = pin_cpu(&cores) {
-            eprintln!("warning: failed to pin CPU cores: {}", e);
-        } else {
-            println!("CPU pinned to cores: {:?}", cores);
+        match crate::cpu::choose_cores(cpu) {
+            Ok(cores) => {
+                if let Err(e) = pin_cpu(&cores) {
+                    eprintln!("warning: failed to pin CPU cores: {}", e);
+                } else {
+                    println!("CPU pinned to cores: {:?}", cores);
+                    return cores;
+                }
+            }
+            Err(e) => eprintln!("warning: failed to pin CPU cores: {}", e),
         }
     } else {
         println!("No CPU pinning set for this profile");
     }
+    crate::cpu::usable_cores()
+}
+
+/// set the io priority and scheduling policy from the profile's `[io]`
+/// and `[sched]` sections. like nice, children inherit both.
+pub fn apply_profile_io_sched(profile: &Profile) {
+    if let Some(io) = &profile.io {
+        let class = match io.class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        if let Err(e) = set_io_priority(class, io.level.min(7) as u32) {
+            eprintln!("warning: failed to set io priority: {}", e);
+        }
+    }
+    if let Some(sched) = &profile.sched {
+        let policy = match sched.policy {
+            SchedPolicy::Normal => libc::SCHED_OTHER,
+            SchedPolicy::Batch => libc::SCHED_BATCH,
+            SchedPolicy::Idle => libc::SCHED_IDLE,
+        };
+        if let Err(e) = set_sched_policy(policy) {
+            eprintln!("warning: failed to set scheduling policy: {}", e);
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -124,8 +183,21 @@ pub struct Profile {
     pub name: String,
     pub description: Option<String>,
     pub nice: i32,
+    /// max processes in the session: pids.max under cgroup v2. without it,
+    /// only root sessions run with --user= get it, as RLIMIT_NPROC
+    pub procs: Option<u64>,
+    /// max open files per process (RLIMIT_NOFILE)
+    pub files: Option<u64>,
+    /// cpu seconds each process may use (RLIMIT_CPU)
+    pub cpu_seconds: Option<u64>,
+    /// wall-clock seconds before the whole session is killed
+    pub timeout: Option<u64>,
+    /// -1000 to 1000; above 0 the box is picked by the oom killer before the host
+    pub oom_score_adj: Option<i32>,
     pub memory: MemoryConfig,
     pub cpu: Option<CpuConfig>,
+    pub io: Option<IoConfig>,
+    pub sched: Option<SchedConfig>,
 }
 
 impl Profile {
@@ -138,9 +210,47 @@ impl Profile {
     }
 
     fn cpu_display(&self) -> String {
-        match &self.cpu {
-            None => "all".into(),
-            Some(n) => n.cores.to_string(),
+        let Some(cpu) = &self.cpu else {
+            return "all".into();
+        };
+        let mut out = match (&cpu.set, cpu.cores) {
+            (Some(set), _) => set.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(","),
+            (None, 0) => "all".into(),
+            (None, n) => n.to_string(),
+        };
+        if cpu.set.is_none() && cpu.select != CoreSelect::Any {
+            out.push_str(if cpu.select == CoreSelect::Big { " big" } else { " little" });
+        }
+        if let Some(q) = cpu.quota {
+            out.push_str(&format!(" @{q}%"));
+        }
+        out
+    }
+
+    fn io_display(&self) -> String {
+        let Some(io) = &self.io else {
+            return "default".into();
+        };
+        let mut out = match io.class {
+            IoClass::Realtime => format!("rt{}", io.level),
+            IoClass::BestEffort => format!("be{}", io.level),
+            IoClass::Idle => "idle".into(),
+        };
+        if let Some(w) = io.weight {
+            out.push_str(&format!(" w{w}"));
+        }
+        if io.read_mbps.is_some() || io.write_mbps.is_some() {
+            let rate = |r: Option<u64>| r.map(|r| r.to_string()).unwrap_or_else(|| "-".into());
+            out.push_str(&format!(" {}/{}M", rate(io.read_mbps), rate(io.write_mbps)));
+        }
+        out
+    }
+
+    fn sched_display(&self) -> String {
+        match self.sched.as_ref().map(|s| s.policy) {
+            None | Some(SchedPolicy::Normal) => "normal".into(),
+            Some(SchedPolicy::Batch) => "batch".into(),
+            Some(SchedPolicy::Idle) => "idle".into(),
         }
     }
 
@@ -173,16 +283,55 @@ impl Profile {
     fn cpu_weight(&self) -> u64 {
         match &self.cpu {
             None => 0,                    // unlimited
-            Some(cpu) => 1000 - cpu.cores as u64 * 100,
+            Some(cpu) => {
+                let n = cpu.count() as u64;
+                let mut weight = if n == 0 { 0 } else { 1000_u64.saturating_sub(n * 100) };
+                if cpu.select == CoreSelect::Little {
+                    weight += 100;
+                }
+                // 50% of the cores costs about as much as losing a few of them
+                if let Some(q) = cpu.quota {
+                    weight += (100 - q.min(100)) as u64 * 5;
+                }
+                weight
+            }
         }
     }
     fn nice_weight(&self) -> u64 {
         self.nice as u64
     }
+    fn io_weight(&self) -> u64 {
+        let Some(io) = &self.io else {
+            return 0;
+        };
+        let mut weight = match io.class {
+            IoClass::Realtime => 0,
+            IoClass::BestEffort => io.level.min(7) as u64 * 25,
+            IoClass::Idle => 400,
+        };
+        // io.weight defaults to 100; only less than that holds the box back
+        if let Some(w) = io.weight {
+            weight += 100_u64.saturating_sub(w as u64) * 2;
+        }
+        // a cap of 1 MB/s hurts far more than one of 100 MB/s
+        for mbps in [io.read_mbps, io.write_mbps].into_iter().flatten() {
+            weight += (1000 / mbps.max(1)).min(300);
+        }
+        weight
+    }
+    fn sched_weight(&self) -> u64 {
+        match self.sched.as_ref().map(|s| s.policy) {
+            None | Some(SchedPolicy::Normal) => 0,
+            Some(SchedPolicy::Batch) => 50,
+            Some(SchedPolicy::Idle) => 300,
+        }
+    }
     fn score(&self) -> u64 {
         self.memory_weight() * 10
             + self.cpu_weight() * 2
             + self.nice_weight()
+            + self.io_weight()
+            + self.sched_weight()
     }
 }
 
@@ -199,9 +348,87 @@ pub enum MemoryConfig {
     Fixed { mb: u64 },
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct CpuConfig {
+    /// how many cores to pin to; 0 = every core `select` allows
+    #[serde(default)]
     pub cores: usize,
+    /// exact cores to pin to; beats `cores` and `select`
+    pub set: Option<Vec<usize>>,
+    #[serde(default)]
+    pub select: CoreSelect,
+    /// share of the chosen cores' time the box may use, in percent (cgroup v2 only)
+    pub quota: Option<u32>,
+}
+
+impl CpuConfig {
+    /// cores asked for; 0 = all
+    fn count(&self) -> usize {
+        self.set.as_ref().map(|s| s.len()).unwrap_or(self.cores)
+    }
+}
+
+/// `[io]`: the box's io priority, plus io.weight/io.max under cgroup v2
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IoConfig {
+    #[serde(default)]
+    pub class: IoClass,
+    /// 0 (first served) to 7; ignored by the idle class
+    #[serde(default = "default_io_level")]
+    pub level: u8,
+    /// io.weight, 1 to 10000 (100 is the default)
+    pub weight: Option<u16>,
+    /// io.max caps, in MB/s, on the disk holding ONYX_DIR
+    pub read_mbps: Option<u64>,
+    pub write_mbps: Option<u64>,
+}
+
+fn default_io_level() -> u8 {
+    4
+}
+
+impl Default for IoConfig {
+    fn default() -> Self {
+        Self { class: IoClass::BestEffort, level: default_io_level(), weight: None, read_mbps: None, write_mbps: None }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    /// needs root
+    Realtime,
+    #[default]
+    BestEffort,
+    /// only gets the disk when nothing else wants it
+    Idle,
+}
+
+/// `[sched]`: the cpu scheduling policy the box runs under
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SchedConfig {
+    pub policy: SchedPolicy,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SchedPolicy {
+    #[default]
+    Normal,
+    /// SCHED_BATCH: no wakeup boost, for throughput jobs
+    Batch,
+    /// SCHED_IDLE: only runs when the cpu has nothing better to do
+    Idle,
+}
+
+/// which kind of core `cores` are taken from, by the big-core heuristic in cpu.rs
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CoreSelect {
+    #[default]
+    Any,
+    Big,
+    Little,
 }
 
 pub fn cmd(args: Vec<String>) {
@@ -269,6 +496,154 @@ fn parse_memory(s: &str) -> MemoryConfig {
     }
 }
 
+/// the `--cpu-*` flags shared by create and edit; false if `arg` isn't one
+fn set_cpu_flag(cpu: &mut Option<CpuConfig>, arg: &str) -> bool {
+    if let Some(val) = arg.strip_prefix("--cpu-cores=") {
+        let cfg = cpu.get_or_insert_with(CpuConfig::default);
+        cfg.cores = val.parse().unwrap_or(cfg.cores);
+    } else if let Some(val) = arg.strip_prefix("--cpu-set=") {
+        match crate::cpu::parse_core_list(val) {
+            Some(set) if !set.is_empty() => cpu.get_or_insert_with(CpuConfig::default).set = Some(set),
+            _ if val.is_empty() => {
+                if let Some(cfg) = cpu.as_mut() {
+                    cfg.set = None;
+                }
+            }
+            _ => eprintln!("Invalid core list '{}', expected e.g. 0-3,6", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--cpu-select=") {
+        match val {
+            "any" => cpu.get_or_insert_with(CpuConfig::default).select = CoreSelect::Any,
+            "big" => cpu.get_or_insert_with(CpuConfig::default).select = CoreSelect::Big,
+            "little" => cpu.get_or_insert_with(CpuConfig::default).select = CoreSelect::Little,
+            _ => eprintln!("Invalid core selector '{}', expected big, little or any", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--cpu-quota=") {
+        match val.trim_end_matches('%').parse::<u32>() {
+            Ok(q) if (1..=100).contains(&q) => cpu.get_or_insert_with(CpuConfig::default).quota = Some(q),
+            _ if val.is_empty() => {
+                if let Some(cfg) = cpu.as_mut() {
+                    cfg.quota = None;
+                }
+            }
+            _ => eprintln!("Invalid cpu quota '{}', expected a percentage from 1 to 100", val),
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+/// "90", "90s", "15m", "2h" or "1d", in seconds
+fn parse_duration(s: &str) -> Option<u64> {
+    let (num, unit) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1),
+        (i, 'm') => (&s[..i], 60),
+        (i, 'h') => (&s[..i], 3600),
+        (i, 'd') => (&s[..i], 86400),
+        _ => (s, 1),
+    };
+    num.parse::<u64>().ok().filter(|n| *n > 0).map(|n| n * unit)
+}
+
+/// the process, file, time and oom flags shared by create and edit; false if `arg` isn't one.
+/// an empty value clears the limit.
+fn set_limit_flag(profile: &mut Profile, arg: &str) -> bool {
+    let count = |val: &str, what: &str| -> Option<Option<u64>> {
+        if val.is_empty() {
+            return Some(None);
+        }
+        match val.parse::<u64>() {
+            Ok(n) if n > 0 => Some(Some(n)),
+            _ => {
+                eprintln!("Invalid {} '{}', expected a positive number", what, val);
+                None
+            }
+        }
+    };
+
+    if let Some(val) = arg.strip_prefix("--procs=") {
+        if let Some(v) = count(val, "process limit") {
+            profile.procs = v;
+        }
+    } else if let Some(val) = arg.strip_prefix("--files=") {
+        if let Some(v) = count(val, "open file limit") {
+            profile.files = v;
+        }
+    } else if let Some(val) = arg.strip_prefix("--cpu-time=") {
+        match parse_duration(val) {
+            Some(secs) => profile.cpu_seconds = Some(secs),
+            None if val.is_empty() => profile.cpu_seconds = None,
+            None => eprintln!("Invalid cpu time '{}', expected e.g. 600, 30m or 2h", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--timeout=") {
+        match parse_duration(val) {
+            Some(secs) => profile.timeout = Some(secs),
+            None if val.is_empty() => profile.timeout = None,
+            None => eprintln!("Invalid timeout '{}', expected e.g. 600, 30m or 2h", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--oom-score-adj=") {
+        match val.parse::<i32>() {
+            Ok(adj) if (-1000..=1000).contains(&adj) => profile.oom_score_adj = Some(adj),
+            _ if val.is_empty() => profile.oom_score_adj = None,
+            _ => eprintln!("Invalid oom score adjustment '{}', expected -1000 to 1000", val),
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+/// the `--io-*` and `--sched=` flags shared by create and edit; false if `arg` isn't one
+fn set_io_sched_flag(profile: &mut Profile, arg: &str) -> bool {
+    if let Some(val) = arg.strip_prefix("--io-class=") {
+        let class = match val {
+            "realtime" => IoClass::Realtime,
+            "best-effort" => IoClass::BestEffort,
+            "idle" => IoClass::Idle,
+            _ => {
+                eprintln!("Invalid io class '{}', expected realtime, best-effort or idle", val);
+                return true;
+            }
+        };
+        profile.io.get_or_insert_with(IoConfig::default).class = class;
+    } else if let Some(val) = arg.strip_prefix("--io-level=") {
+        match val.parse::<u8>() {
+            Ok(level) if level <= 7 => profile.io.get_or_insert_with(IoConfig::default).level = level,
+            _ => eprintln!("Invalid io level '{}', expected 0 to 7", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--io-weight=") {
+        match val.parse::<u16>() {
+            Ok(w) if (1..=10000).contains(&w) => profile.io.get_or_insert_with(IoConfig::default).weight = Some(w),
+            _ => eprintln!("Invalid io weight '{}', expected 1 to 10000", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--io-read=") {
+        match val.parse::<u64>() {
+            Ok(mbps) if mbps > 0 => profile.io.get_or_insert_with(IoConfig::default).read_mbps = Some(mbps),
+            _ => eprintln!("Invalid read cap '{}', expected MB/s", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--io-write=") {
+        match val.parse::<u64>() {
+            Ok(mbps) if mbps > 0 => profile.io.get_or_insert_with(IoConfig::default).write_mbps = Some(mbps),
+            _ => eprintln!("Invalid write cap '{}', expected MB/s", val),
+        }
+    } else if let Some(val) = arg.strip_prefix("--sched=") {
+        let policy = match val {
+            "normal" => SchedPolicy::Normal,
+            "batch" => SchedPolicy::Batch,
+            "idle" => SchedPolicy::Idle,
+            _ => {
+                eprintln!("Invalid scheduling policy '{}', expected normal, batch or idle", val);
+                return true;
+            }
+        };
+        profile.sched = Some(SchedConfig { policy });
+    } else {
+        return false;
+    }
+    true
+}
+
 fn profile_path(name: &str) -> std::path::PathBuf {
     // ONYX_DIR is assumed to be a PathBuf
     ONYX_DIR.join("profiles").join(format!("{}.toml", name))
@@ -316,8 +691,15 @@ fn create_profile_from_args(args: Vec<String>) {
         name: name.clone(),
         description: None,
         nice: 0,
+        procs: None,
+        files: None,
+        cpu_seconds: None,
+        timeout: None,
+        oom_score_adj: None,
         memory: MemoryConfig::Unlimited,
         cpu: None,
+        io: None,
+        sched: None,
     };
 
     for arg in &args[4..] {
@@ -327,9 +709,8 @@ fn create_profile_from_args(args: Vec<String>) {
             profile.nice = val.parse().unwrap_or(profile.nice);
         } else if let Some(val) = arg.strip_prefix("--memory=") {
             profile.memory = parse_memory(val);
-        } else if let Some(val) = arg.strip_prefix("--cpu-cores=") {
-            let cores = val.parse().unwrap_or(profile.cpu.as_ref().map(|c| c.cores).unwrap_or(0));
-            profile.cpu = Some(CpuConfig { cores });
+        } else if !set_cpu_flag(&mut profile.cpu, arg) && !set_io_sched_flag(&mut profile, arg) {
+            set_limit_flag(&mut profile, arg);
         }
     }
 
@@ -338,12 +719,12 @@ fn create_profile_from_args(args: Vec<String>) {
 }
 
 fn edit_profile_from_args(args: Vec<String>) {
-    if args.is_empty() {
+    if args.len() < 4 {
         eprintln!("Usage: profile edit <name> [--flag=value...]");
         return;
     }
 
-    let name = &args[0];
+    let name = &args[3];
     let mut profile = match load_profile(name) {
         Some(p) => p,
         None => {
@@ -352,19 +733,39 @@ fn edit_profile_from_args(args: Vec<String>) {
         }
     };
 
-    for arg in &args[1..] {
+    for arg in &args[4..] {
         if let Some(val) = arg.strip_prefix("--description=") {
             profile.description = Some(val.to_string());
         } else if let Some(val) = arg.strip_prefix("--nice=") {
             profile.nice = val.parse().unwrap_or(profile.nice);
         } else if let Some(val) = arg.strip_prefix("--memory=") {
             profile.memory = parse_memory(val);
-        } else if let Some(val) = arg.strip_prefix("--cpu-cores=") {
-            let cores = val.parse().unwrap_or(profile.cpu.as_ref().map(|c| c.cores).unwrap_or(0));
-            profile.cpu = Some(CpuConfig { cores });
+        } else if !set_cpu_flag(&mut profile.cpu, arg) && !set_io_sched_flag(&mut profile, arg) {
+            set_limit_flag(&mut profile, arg);
         }
     }
 
     save_profile(&profile);
     println!("Profile '{}' updated", name);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("90s"), Some(90));
+        assert_eq!(parse_duration("15m"), Some(900));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1d"), Some(86400));
+
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("5w"), None);
+        assert_eq!(parse_duration("1.5h"), None);
+    }
+}
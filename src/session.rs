@@ -0,0 +1,114 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::helpers::ONYX_DIR;
+
+//=== session tracking ===//
+// every open/exec drops a pid file in ONYX_DIR/delta/<uid>/<box>/sessions
+// for as long as it runs, so commands that rewrite a delta can tell
+// whether somebody is still using it.
+
+fn sessions_dir(uid: &str, box_name: &str) -> PathBuf {
+    ONYX_DIR.join("delta").join(uid).join(box_name).join("sessions")
+}
+
+fn pid_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// RAII marker for a running session
+pub struct SessionLock {
+    path: PathBuf,
+}
+
+impl SessionLock {
+    pub fn acquire(uid: &str, box_name: &str) -> io::Result<Self> {
+        let dir = sessions_dir(uid, box_name);
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(std::process::id().to_string());
+        fs::write(&path, "")?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// pids of live sessions on `uid`'s delta of `box_name`.
+/// stale pid files from crashed sessions are cleaned up on the way.
+pub fn active(uid: &str, box_name: &str) -> Vec<u32> {
+    let mut pids = Vec::new();
+
+    let Ok(entries) = fs::read_dir(sessions_dir(uid, box_name)) else {
+        return pids;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else {
+            continue;
+        };
+        if pid_alive(pid) {
+            pids.push(pid);
+        } else {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+
+    pids
+}
+
+/// every process below `pid`, children first in no particular order
+pub fn descendants(pid: u32) -> Vec<u32> {
+    let mut parents = Vec::new();
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.filter_map(Result::ok) {
+            let Some(child) = entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else {
+                continue;
+            };
+            // the ppid is the second field after the parenthesised comm, which may hold spaces
+            let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            let ppid = stat
+                .rsplit_once(')')
+                .and_then(|(_, rest)| rest.split_whitespace().nth(1))
+                .and_then(|p| p.parse::<u32>().ok());
+            if let Some(ppid) = ppid {
+                parents.push((child, ppid));
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut frontier = vec![pid];
+    while let Some(parent) = frontier.pop() {
+        for &(child, _) in parents.iter().filter(|(_, p)| *p == parent) {
+            out.push(child);
+            frontier.push(child);
+        }
+    }
+    out
+}
+
+/// live sessions on `box_name` across every user's delta, as (uid, pid)
+pub fn active_any(box_name: &str) -> Vec<(String, u32)> {
+    let mut out = Vec::new();
+
+    let Ok(uids) = fs::read_dir(ONYX_DIR.join("delta")) else {
+        return out;
+    };
+
+    for uid in uids.filter_map(Result::ok) {
+        let uid = uid.file_name().to_string_lossy().to_string();
+        for pid in active(&uid, box_name) {
+            out.push((uid.clone(), pid));
+        }
+    }
+
+    out
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use crate::helpers::{errln, infoln, time_get, BLUE, BLUEB, DIM, ESC, ONYX_DIR};
+use crate::session;
+use crate::tar::{self, ExportManifest};
+
+//=== delta snapshots ===//
+// a snapshot is the raw upper layer of ONYX_DIR/delta/<uid>/<box>, packed
+// (whiteouts and all) into ONYX_DIR/snapshots/<uid>/<box>/<tag>.tar.zst.
+// they live outside delta/ so merging or resetting a delta keeps them.
+
+fn snapshot_dir(uid: &str, box_name: &str) -> PathBuf {
+    ONYX_DIR.join("snapshots").join(uid).join(box_name)
+}
+
+fn snapshot_path(uid: &str, box_name: &str, tag: &str) -> PathBuf {
+    snapshot_dir(uid, box_name).join(format!("{}.tar.zst", tag))
+}
+
+fn valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && !tag.starts_with('.')
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn refuse_if_active(uid: &str, box_name: &str) -> io::Result<()> {
+    let pids = session::active(uid, box_name);
+    if !pids.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("box '{}' has running sessions for this delta (pids {:?}); exit them first", box_name, pids),
+        ));
+    }
+    Ok(())
+}
+
+/// save the current upper layer under `tag`
+pub fn create(uid: &str, box_name: &str, tag: &str) -> io::Result<()> {
+    if !valid_tag(tag) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid tag '{}' (use letters, digits, '-', '_' and '.')", tag)));
+    }
+
+    let out = snapshot_path(uid, box_name, tag);
+    if out.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("snapshot '{}' already exists", tag)));
+    }
+
+    // reading the upper under a live overlay works, but it may be mid-write
+    if !session::active(uid, box_name).is_empty() {
+        errln("box", "box is running; the snapshot may catch files mid-write");
+    }
+
+    fs::create_dir_all(snapshot_dir(uid, box_name))?;
+
+    let upper = ONYX_DIR.join("delta").join(uid).join(box_name).join("upper");
+    let manifest = ExportManifest {
+        format: 1,
+        name: box_name.to_string(),
+        exported: time_get(),
+        onyx_version: crate::doctor::VERSION.to_string(),
+        delta_user: None,
+        delta_uid: Some(uid.to_string()),
+        settings: None,
+    };
+
+    infoln("box", &format!("snapshotting delta of '{}' as '{}'...", box_name, tag));
+    tar::pack_dir(&upper, &out, &manifest)?;
+    infoln("box", &format!("snapshot '{}' saved", tag));
+    Ok(())
+}
+
+/// print every snapshot of `uid`'s delta for `box_name`
+pub fn list(uid: &str, box_name: &str) -> io::Result<()> {
+    let dir = snapshot_dir(uid, box_name);
+
+    let mut snaps: Vec<(String, u64, u64)> = Vec::new();
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(tag) = name.strip_suffix(".tar.zst") else {
+                continue;
+            };
+            let meta = entry.metadata()?;
+            let secs = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            snaps.push((tag.to_string(), secs, meta.len()));
+        }
+    }
+
+    println!("{BLUEB}[>== snapshots: {} ==<]{ESC}", box_name);
+
+    if snaps.is_empty() {
+        println!("    {DIM}no snapshots{ESC}");
+        return Ok(());
+    }
+
+    snaps.sort_by_key(|s| s.1);
+    for (tag, secs, size) in snaps {
+        let when = time_format::strftime_local("%Y-%m-%d %H:%M:%S", secs as i64).unwrap_or_else(|_| "unknown".into());
+        println!("{BLUEB}{}:{ESC}", tag);
+        println!("    {BLUE}[taken]{ESC} {}", when);
+        println!("    {BLUE}[size]{ESC} {:.1} MB", size as f64 / 1024.0 / 1024.0);
+    }
+
+    Ok(())
+}
+
+/// replace the current upper layer with the one saved under `tag`
+pub fn rollback(uid: &str, box_name: &str, tag: &str) -> io::Result<()> {
+    let archive = snapshot_path(uid, box_name, tag);
+    if !valid_tag(tag) || !archive.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("snapshot '{}' not found", tag)));
+    }
+
+    refuse_if_active(uid, box_name)?;
+
+    let delta = ONYX_DIR.join("delta").join(uid).join(box_name);
+    let upper = delta.join("upper");
+    let staged = delta.join("upper.rollback");
+    let old = delta.join("upper.old");
+
+    for stale in [&staged, &old] {
+        if stale.exists() {
+            fs::remove_dir_all(stale)?;
+        }
+    }
+
+    infoln("box", &format!("restoring snapshot '{}' of '{}'...", tag, box_name));
+
+    // unpack next to the live upper first, then swap the two in
+    if let Err(e) = tar::extract_archive(&archive, &staged) {
+        let _ = fs::remove_dir_all(&staged);
+        return Err(e);
+    }
+
+    if upper.exists() {
+        fs::rename(&upper, &old)?;
+    }
+    if let Err(e) = fs::rename(&staged, &upper) {
+        // put the previous upper back so nothing is lost
+        let _ = fs::rename(&old, &upper);
+        return Err(e);
+    }
+
+    if old.exists() && let Err(e) = fs::remove_dir_all(&old) {
+        errln("box", &format!("failed to remove old upper {}: {}", old.display(), e));
+    }
+
+    // overlay workdirs must not outlive the upper they were paired with
+    let work = delta.join("work");
+    if work.exists() && let Err(e) = fs::remove_dir_all(&work) {
+        errln("box", &format!("failed to clear workdir {}: {}", work.display(), e));
+    }
+
+    infoln("box", &format!("delta of '{}' rolled back to '{}'", box_name, tag));
+    Ok(())
+}
@@ -1,20 +1,650 @@
-use std::fs::File;
-use std::io;
-use tar::Archive;
-use zstd::stream::read::Decoder;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
-pub fn extract_tar_zst(path: &str, dest: &str) -> io::Result<()> {
-    // open the .tar.zst file
-    let tar_zst = File::open(path)?;
+use ::tar::{Archive, Builder, EntryType, Header, HeaderMode};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
 
-    // create a streaming decoder for zstd
-    let decoder = Decoder::new(tar_zst)?;  
+use walkdir::WalkDir;
 
-    // create a tar archive reader
-    let mut archive = Archive::new(decoder);
+use crate::copy;
+use crate::helpers::{errln, rooted};
+use crate::layer;
+use crate::manifest::BoxManifest;
+
+/// name of the metadata entry onyx puts at the top of exported archives
+pub const MANIFEST_NAME: &str = ".onyx-manifest.toml";
+
+/// small description of an exported box, stored inside the archive
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ExportManifest {
+    pub format: u32,
+    pub name: String,
+    pub exported: String,
+    pub onyx_version: String,
+    pub delta_user: Option<String>,
+    pub delta_uid: Option<String>,
+    /// the box's own box.toml settings, if it had one
+    #[serde(default)]
+    pub settings: Option<BoxManifest>,
+}
+
+/// compression formats we know how to stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+    Xz,
+    None,
+}
+
+impl Compression {
+    /// pick an output compression from the file name, zstd by default
+    pub fn from_extension(path: &Path) -> Compression {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if name.ends_with(".gz") || name.ends_with(".tgz") {
+            Compression::Gzip
+        } else if name.ends_with(".xz") || name.ends_with(".txz") {
+            Compression::Xz
+        } else if name.ends_with(".tar") {
+            Compression::None
+        } else {
+            Compression::Zstd
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::None => "none",
+        }
+    }
+}
+
+/// sniff the compression from the first bytes of the archive
+/// (extensions lie, magic numbers don't)
+fn detect_compression(head: &[u8]) -> io::Result<Compression> {
+    if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Ok(Compression::Zstd)
+    } else if head.starts_with(&[0x1f, 0x8b]) {
+        Ok(Compression::Gzip)
+    } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Ok(Compression::Xz)
+    } else if head.len() >= 262 && &head[257..262] == b"ustar" {
+        Ok(Compression::None)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unrecognized archive format (expected .tar, .tar.zst, .tar.gz or .tar.xz)",
+        ))
+    }
+}
+
+/// open `path` and wrap it in the right decoder.
+/// the returned reader yields the raw tar stream.
+fn open_decoder(path: &Path, pb: &ProgressBar) -> io::Result<(Box<dyn Read>, Compression)> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::with_capacity(64 * 1024, pb.wrap_read(file));
+    let compression = detect_compression(reader.fill_buf()?)?;
+
+    let decoder: Box<dyn Read> = match compression {
+        Compression::Zstd => Box::new(ZstdDecoder::with_buffer(reader)?),
+        Compression::Gzip => Box::new(GzDecoder::new(reader)),
+        Compression::Xz => Box::new(XzDecoder::new(reader)),
+        Compression::None => Box::new(reader),
+    };
+
+    Ok((decoder, compression))
+}
 
-    // extract all files to destination
-    archive.unpack(dest)?;  
+/// turns an archive member path into a path under `dest`.
+/// leading '/' and '.' are dropped, '..' is refused outright.
+fn sanitize_entry_path(dest: &Path, entry_path: &Path) -> io::Result<PathBuf> {
+    let mut out = dest.to_path_buf();
+    for part in entry_path.components() {
+        match part {
+            Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+            Component::ParentDir => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("refusing path-traversal entry: {}", entry_path.display()),
+                ));
+            }
+            Component::Normal(p) => out.push(p),
+        }
+    }
+    Ok(out)
+}
 
+/// make sure `path` doesn't resolve outside of `dest` through a symlink
+/// planted by an earlier entry
+fn ensure_inside(dest: &Path, path: &Path) -> io::Result<()> {
+    let canon_dest = dest.canonicalize()?;
+    let canon_path = path.canonicalize()?;
+    if !canon_path.starts_with(&canon_dest) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("refusing entry that escapes the box: {}", path.display()),
+        ));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// create a device node / fifo, which the tar crate would otherwise
+/// unpack as an empty regular file
+fn make_special(dst: &Path, kind: EntryType, mode: u32, major: u32, minor: u32) -> io::Result<()> {
+    let file_type = match kind {
+        EntryType::Char => libc::S_IFCHR,
+        EntryType::Block => libc::S_IFBLK,
+        _ => libc::S_IFIFO,
+    };
+
+    if fs::symlink_metadata(dst).is_ok() {
+        fs::remove_file(dst)?;
+    }
+
+    copy::mknod(dst, file_type | (mode & 0o7777), libc::makedev(major, minor))
+}
+
+/// SCHILY.xattr.* records attached to an entry
+fn entry_xattrs<R: Read>(entry: &mut ::tar::Entry<R>) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut out = Vec::new();
+    if let Some(exts) = entry.pax_extensions()? {
+        for ext in exts {
+            let ext = ext?;
+            if let Ok(key) = ext.key()
+                && let Some(name) = key.strip_prefix("SCHILY.xattr.")
+            {
+                out.push((name.to_string(), ext.value_bytes().to_vec()));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// best-effort xattr restore, returns how many couldn't be set
+fn apply_xattrs(path: &Path, xattrs: &[(String, Vec<u8>)]) -> usize {
+    xattrs
+        .iter()
+        .filter(|(name, value)| xattr::set(path, name, value).is_err())
+        .count()
+}
+
+/// stream a (possibly compressed) tarball straight into `dest`.
+/// keeps modes, ownership (when root), symlinks, hardlinks and device nodes.
+/// returns the detected compression and the onyx manifest, if the archive has one.
+pub fn extract_archive(path: &Path, dest: &Path) -> io::Result<(Compression, Option<ExportManifest>)> {
+    let total = fs::metadata(path)?.len();
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::with_template("{spinner:.cyan.bold} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+            .unwrap()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏✔")
+            .progress_chars("## "),
+    );
+
+    let (decoder, compression) = open_decoder(path, &pb)?;
+    let root = rooted();
+
+    fs::create_dir_all(dest)?;
+
+    let mut archive = Archive::new(decoder);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    // chown only works for root; as a normal user everything is ours anyway.
+    // xattrs are applied by hand below so one unsettable key isn't fatal
+    archive.set_preserve_ownerships(root);
+    archive.set_unpack_xattrs(false);
+    archive.set_mask(0);
+    archive.set_overwrite(true);
+
+    // directories are applied last (deepest first) so read-only dirs
+    // don't block their own children, same as tar::Archive::unpack
+    let mut directories = Vec::new();
+    let mut skipped_devices = 0;
+    let mut skipped_xattrs = 0;
+    let mut manifest = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let target = sanitize_entry_path(dest, &entry_path)?;
+
+        // our own metadata never lands in the rootfs
+        if target == dest.join(MANIFEST_NAME) {
+            let mut data = String::new();
+            entry.read_to_string(&mut data)?;
+            manifest = toml::from_str(&data).ok();
+            continue;
+        }
+
+        let kind = entry.header().entry_type();
+
+        // hardlink sources must stay inside the box as well
+        if kind.is_hard_link() && let Some(link) = entry.link_name()? {
+            sanitize_entry_path(dest, &link)?;
+        }
+
+        let xattrs = entry_xattrs(&mut entry)?;
+
+        match kind {
+            EntryType::Directory => {
+                directories.push((entry, target, xattrs));
+                continue;
+            }
+            EntryType::Char | EntryType::Block | EntryType::Fifo => {
+                if let Some(parent) = target.parent() {
+                    // check the deepest existing ancestor before creating anything
+                    let mut existing = parent;
+                    while fs::symlink_metadata(existing).is_err() {
+                        existing = existing.parent().unwrap_or(dest);
+                    }
+                    ensure_inside(dest, existing)?;
+                    fs::create_dir_all(parent)?;
+                }
+
+                let header = entry.header();
+                let mode = header.mode()?;
+                // fifos usually carry blank device fields
+                let major = header.device_major().ok().flatten().unwrap_or(0);
+                let minor = header.device_minor().ok().flatten().unwrap_or(0);
+
+                match make_special(&target, kind, mode, major, minor) {
+                    Ok(_) => {
+                        if root {
+                            copy::chown_nofollow(&target, header.uid()?, header.gid()?)?;
+                        }
+                    }
+                    Err(e) if !root && e.raw_os_error() == Some(libc::EPERM) => {
+                        skipped_devices += 1;
+                    }
+                    Err(e) => {
+                        return Err(io::Error::new(
+                            e.kind(),
+                            format!("failed to create {}: {}", target.display(), e),
+                        ));
+                    }
+                }
+            }
+            _ => {
+                entry.unpack_in(dest)?;
+            }
+        }
+
+        skipped_xattrs += apply_xattrs(&target, &xattrs);
+    }
+
+    for (mut dir, target, xattrs) in directories.into_iter().rev() {
+        dir.unpack_in(dest)?;
+        skipped_xattrs += apply_xattrs(&target, &xattrs);
+    }
+
+    pb.finish_and_clear();
+
+    if skipped_devices > 0 {
+        errln(
+            "box",
+            &format!("skipped {} device node(s): only root can create them", skipped_devices),
+        );
+    }
+
+    if skipped_xattrs > 0 {
+        errln(
+            "box",
+            &format!("skipped {} extended attribute(s) this user or filesystem can't set", skipped_xattrs),
+        );
+    }
+
+    Ok((compression, manifest))
+}
+
+//=== export ===//
+/// compressed output stream; each encoder needs an explicit finish
+enum Sink {
+    Zstd(ZstdEncoder<'static, File>),
+    Gzip(GzEncoder<File>),
+    Xz(XzEncoder<File>),
+    Plain(File),
+}
+
+impl Sink {
+    fn new(file: File, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Zstd => Sink::Zstd(ZstdEncoder::new(file, 0)?),
+            Compression::Gzip => Sink::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Compression::Xz => Sink::Xz(XzEncoder::new(file, 6)),
+            Compression::None => Sink::Plain(file),
+        })
+    }
+
+    fn finish(self) -> io::Result<File> {
+        match self {
+            Sink::Zstd(e) => e.finish(),
+            Sink::Gzip(e) => e.finish(),
+            Sink::Xz(e) => e.finish(),
+            Sink::Plain(f) => Ok(f),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Zstd(e) => e.write(buf),
+            Sink::Gzip(e) => e.write(buf),
+            Sink::Xz(e) => e.write(buf),
+            Sink::Plain(f) => f.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Zstd(e) => e.flush(),
+            Sink::Gzip(e) => e.flush(),
+            Sink::Xz(e) => e.flush(),
+            Sink::Plain(f) => f.flush(),
+        }
+    }
+}
+
+/// one pax record: "<len> <key>=<value>\n", where len counts itself
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let body = key.len() + value.len() + 3; // ' ', '=', '\n'
+    let mut len = body + body.to_string().len();
+    if len.to_string().len() != body.to_string().len() {
+        len += 1;
+    }
+
+    let mut rec = format!("{} {}=", len, key).into_bytes();
+    rec.extend_from_slice(value);
+    rec.push(b'\n');
+    rec
+}
+
+/// write the file's xattrs as a pax extension header (SCHILY.xattr.*),
+/// which is what GNU tar and our extractor understand.
+/// overlay bookkeeping xattrs are only kept when archiving a raw layer.
+fn append_xattrs<W: Write>(builder: &mut Builder<W>, path: &Path, raw_layer: bool) -> io::Result<()> {
+    let names = match xattr::list(path) {
+        Ok(n) => n,
+        Err(_) => return Ok(()), // fs without xattr support
+    };
+
+    let mut data = Vec::new();
+    for name in names {
+        let key = name.to_string_lossy().to_string();
+        if !raw_layer && layer::is_overlay_xattr(&key) {
+            continue;
+        }
+        if let Ok(Some(value)) = xattr::get(path, &name) {
+            data.extend(pax_record(&format!("SCHILY.xattr.{}", key), &value));
+        }
+    }
+
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XHeader);
+    header.set_path("././@PaxHeader")?;
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, data.as_slice())
+}
+
+/// add a single filesystem entry at `rel`, turning repeated inodes into hardlinks
+fn append_entry<W: Write>(
+    builder: &mut Builder<W>,
+    rel: &Path,
+    path: &Path,
+    meta: &fs::Metadata,
+    links: &mut HashMap<(u64, u64), PathBuf>,
+    raw_layer: bool,
+) -> io::Result<()> {
+    let ft = meta.file_type();
+    if ft.is_socket() {
+        return Ok(()); // sockets can't be archived
+    }
+
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(meta, HeaderMode::Complete);
+
+    if !ft.is_dir() && meta.nlink() > 1 {
+        let key = (meta.dev(), meta.ino());
+        if let Some(first) = links.get(&key) {
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            return builder.append_link(&mut header, rel, first);
+        }
+        links.insert(key, rel.to_path_buf());
+    }
+
+    append_xattrs(builder, path, raw_layer)?;
+
+    if ft.is_symlink() {
+        header.set_size(0);
+        let target = fs::read_link(path)?;
+        builder.append_link(&mut header, rel, target)
+    } else if ft.is_file() {
+        let file = File::open(path)?;
+        builder.append_data(&mut header, rel, file)
+    } else {
+        if ft.is_char_device() || ft.is_block_device() {
+            header.set_device_major(libc::major(meta.rdev()))?;
+            header.set_device_minor(libc::minor(meta.rdev()))?;
+        }
+        header.set_size(0);
+        builder.append_data(&mut header, rel, io::empty())
+    }
+}
+
+/// write the merged view of `layers` (top to bottom) to `out` as a tarball,
+/// with `manifest` stored as the first entry
+pub fn export_layers(layers: &[PathBuf], out: &Path, manifest: &ExportManifest) -> io::Result<Compression> {
+    write_archive(out, manifest, "Exporting...", |builder, pb| {
+        let mut links = HashMap::new();
+        let mut count: u64 = 0;
+        layer::flatten(layers, |rel, path, meta| {
+            count += 1;
+            if count.is_multiple_of(512) {
+                pb.set_message(format!("Exporting... {} entries", count));
+            }
+            append_entry(builder, rel, path, meta, &mut links, false)
+        })
+    })
+}
+
+/// archive `dir` exactly as it is on disk, whiteouts and overlay xattrs included.
+/// used for snapshots of a delta's upper layer.
+pub fn pack_dir(dir: &Path, out: &Path, manifest: &ExportManifest) -> io::Result<Compression> {
+    write_archive(out, manifest, "Packing...", |builder, _| {
+        let mut links = HashMap::new();
+        if !dir.exists() {
+            return Ok(()); // nothing written yet, so an empty layer
+        }
+        for entry in WalkDir::new(dir).follow_links(false).sort_by_file_name().min_depth(1) {
+            let entry = entry.map_err(io::Error::other)?;
+            let rel = entry.path().strip_prefix(dir).map_err(io::Error::other)?;
+            let meta = entry.metadata().map_err(io::Error::other)?;
+            append_entry(builder, rel, entry.path(), &meta, &mut links, true)?;
+        }
+        Ok(())
+    })
+}
+
+/// shared plumbing for writing an archive: temp file, compression, manifest
+/// entry first, spinner, and an atomic rename once `fill` succeeds
+fn write_archive<F>(out: &Path, manifest: &ExportManifest, msg: &'static str, fill: F) -> io::Result<Compression>
+where
+    F: FnOnce(&mut Builder<Sink>, &ProgressBar) -> io::Result<()>,
+{
+    let compression = Compression::from_extension(out);
+    // next to `out`, so the final rename stays on one filesystem
+    let mut temp_path = out.as_os_str().to_owned();
+    temp_path.push(".onyx-tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::with_template("{spinner:.cyan.bold} {msg}")
+            .unwrap()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏✔")
+    );
+    pb.enable_steady_tick(Duration::from_millis(80));
+    pb.set_message(msg);
+
+    let result = (|| {
+        let sink = Sink::new(File::create(&temp_path)?, compression)?;
+        let mut builder = Builder::new(sink);
+
+        let data = toml::to_string_pretty(manifest)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, MANIFEST_NAME, data.as_bytes())?;
+
+        fill(&mut builder, &pb)?;
+
+        builder.into_inner()?.finish()?.sync_all()
+    })();
+
+    if let Err(e) = result {
+        pb.finish_and_clear();
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    pb.finish_and_clear();
+    fs::rename(&temp_path, out)?;
+    Ok(compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the length prefix counts the whole record, itself included
+    fn check(rec: &[u8]) {
+        let text = String::from_utf8_lossy(rec);
+        let (len, _) = text.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), rec.len(), "{:?}", text);
+        assert_eq!(rec.last(), Some(&b'\n'));
+    }
+
+    #[test]
+    fn pax_records() {
+        assert_eq!(pax_record("a", b"b"), b"6 a=b\n");
+        assert_eq!(pax_record("SCHILY.xattr.user.x", b"\0\xff"), b"26 SCHILY.xattr.user.x=\0\xff\n");
+
+        // right where the length grows a digit
+        for n in 0..1100 {
+            check(&pax_record("k", &vec![b'v'; n]));
+        }
+    }
+
+    /// a header with its name and link name written raw, so the
+    /// builder's own path checks don't stop us from making bad entries
+    fn header(kind: EntryType, name: &str, link: &str, size: u64) -> Header {
+        let mut h = Header::new_ustar();
+        h.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        h.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+        h.set_entry_type(kind);
+        h.set_mode(0o644);
+        h.set_size(size);
+        h.set_uid(0);
+        h.set_gid(0);
+        h.set_mtime(0);
+        h.set_device_major(1).unwrap();
+        h.set_device_minor(3).unwrap();
+        h.set_cksum();
+        h
+    }
+
+    /// write `entries` to a plain tarball in `dir` and extract it into `dir/root`
+    fn extract(dir: &Path, entries: &[(Header, &[u8])]) -> io::Result<Option<ExportManifest>> {
+        let mut builder = Builder::new(Vec::new());
+        for (h, data) in entries {
+            builder.append(h, *data).unwrap();
+        }
+        let archive = dir.join("box.tar");
+        fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+        extract_archive(&archive, &dir.join("root")).map(|(_, m)| m)
+    }
+
+    #[test]
+    fn sanitize_refuses_parent_components() {
+        let dest = Path::new("/box");
+        assert_eq!(sanitize_entry_path(dest, Path::new("/etc/./hosts")).unwrap(), dest.join("etc/hosts"));
+        assert!(sanitize_entry_path(dest, Path::new("../x")).is_err());
+        assert!(sanitize_entry_path(dest, Path::new("etc/../../x")).is_err());
+    }
+
+    #[test]
+    fn ensure_inside_follows_symlinks() {
+        let (dir, outside) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let dest = dir.path();
+        fs::create_dir(dest.join("etc")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), dest.join("link")).unwrap();
+
+        assert!(ensure_inside(dest, &dest.join("etc")).is_ok());
+        assert!(ensure_inside(dest, &dest.join("link")).is_err());
+    }
+
+    #[test]
+    fn extract_refuses_traversal_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = extract(dir.path(), &[(header(EntryType::Regular, "../x", "", 1), b"x")]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!dir.path().join("x").exists());
+
+        let err = extract(dir.path(), &[(header(EntryType::Link, "passwd", "../../etc/passwd", 0), b"")]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(fs::symlink_metadata(dir.path().join("root/passwd")).is_err());
+    }
+
+    #[test]
+    fn extract_refuses_specials_under_a_planted_symlink() {
+        let (dir, outside) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let target = outside.path().to_str().unwrap();
+
+        for kind in [EntryType::Fifo, EntryType::Char] {
+            let entries = [
+                (header(EntryType::Symlink, "link", target, 0), &b""[..]),
+                (header(kind, "link/dev/node", "", 0), &b""[..]),
+            ];
+            let err = extract(dir.path(), &entries).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+            assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn extract_keeps_the_manifest_out_of_the_rootfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = "format = 1\nname = \"deb\"\nexported = \"0\"\nonyx_version = \"0\"\n";
+        let entries = [
+            (header(EntryType::Regular, MANIFEST_NAME, "", toml.len() as u64), toml.as_bytes()),
+            (header(EntryType::Regular, "etc/hostname", "", 4), b"deb\n"),
+        ];
+
+        let manifest = extract(dir.path(), &entries).unwrap().unwrap();
+        assert_eq!(manifest.name, "deb");
+        assert_eq!(fs::read(dir.path().join("root/etc/hostname")).unwrap(), b"deb\n");
+        assert!(fs::symlink_metadata(dir.path().join("root").join(MANIFEST_NAME)).is_err());
+    }
+}